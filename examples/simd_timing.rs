@@ -0,0 +1,44 @@
+//! Times the scalar and SoA force kernels on the same galaxy.
+//! Run with `cargo run --release --example simd_timing [bodies]`.
+//!
+//! Both direct kernels evaluate the force with the same arithmetic, so their ratio is the gain
+//! from the SoA layout and SIMD. The SoA Barnes-Hut also walks the tree once per group of bodies
+//! instead of once per body, which accounts for part of its gain.
+use std::time::{Duration, Instant};
+use barnes_hut::vector::{Scalar, Vector, Vector3D};
+use barnes_hut::nbody::{NBodyConfigBuilder, NBodySimulation3D, MovingBody3D, generate_galaxy, has_avx2};
+use barnes_hut::nbody::{compute_acc_direct, compute_acc_direct_simd, compute_acc_barnes_hut, compute_acc_barnes_hut_simd};
+
+const THETA: Scalar = 0.5;
+
+type Kernel = fn(&mut NBodySimulation3D);
+
+/// Best time of a few runs of `f`.
+fn time(sim: &mut NBodySimulation3D, f: Kernel) -> Duration {
+    (0..5).map(|_| {
+        let start = Instant::now();
+        f(sim);
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    let n: usize = std::env::args().nth(1).and_then(|n| n.parse().ok()).unwrap_or(4000);
+    let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+    let max_r: Vector3D = Vector3D::from_xy(500., 500.);
+    let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(1).build().unwrap();
+    let mut sim: NBodySimulation3D = NBodySimulation3D::empty(n, config);
+    let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+    generate_galaxy(&mut sim, &c);
+
+    println!("{} bodies, AVX2 {}", n, if has_avx2() { "available" } else { "unavailable" });
+    let kernels: [(&str, Kernel, Kernel); 2] = [
+        ("direct", compute_acc_direct, compute_acc_direct_simd),
+        ("barnes-hut", |sim| compute_acc_barnes_hut(sim, THETA), |sim| compute_acc_barnes_hut_simd(sim, THETA)),
+    ];
+    for (name, scalar, simd) in kernels.iter() {
+        let (t_scalar, t_simd) = (time(&mut sim, *scalar), time(&mut sim, *simd));
+        println!("{:>10}: scalar {:>8.2?}, simd {:>8.2?}, speedup {:.1}x",
+            name, t_scalar, t_simd, t_scalar.as_secs_f64() / t_simd.as_secs_f64());
+    }
+}
@@ -4,4 +4,4 @@ pub mod vector;
 pub mod nbody;
 pub mod quadtree;
 
-pub use nbody::{nbody_direct, nbody_barnes_hut};
+pub use nbody::{nbody_direct, nbody_barnes_hut, nbody_hermite};
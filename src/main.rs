@@ -10,20 +10,18 @@ use piston::event_loop::*;
 use piston::input::*;
 use piston::window::WindowSettings;
 
-mod vector;
-mod nbody;
-mod quadtree;
-
-use vector::{
+use barnes_hut::vector::{
     Scalar,
     Vector,
     Vector3D};
-use nbody::{
+use barnes_hut::nbody::{
     generate_galaxy,
     generate_blackhole,
     nbody_barnes_hut,
+    BodyKind,
+    BlackHoleMergerConfig,
     MovingBody3D,
-    NBodyConfig3D,
+    NBodyConfigBuilder,
     NBodySimulation3D
 };
 
@@ -36,6 +34,22 @@ const BLACK: [f32; 4] = [0., 0., 0., 0.0];
 const SOLID_BLACK: [f32; 4] = [0., 0., 0., 1.0];
 const RED1: [f32; 4] = [1., 0., 0., 0.1];
 const WHITE7: [f32; 4] = [1., 1., 1., 0.7];
+const BLUE5: [f32; 4] = [0.4, 0.6, 1., 0.5];
+const PURPLE3: [f32; 4] = [0.6, 0.3, 1., 0.3];
+const GREEN5: [f32; 4] = [0.3, 1., 0.4, 0.5];
+const YELLOW7: [f32; 4] = [1., 0.9, 0.3, 0.7];
+
+/// Color used to draw each kind of body other than black holes.
+fn body_color(kind: BodyKind) -> [f32; 4] {
+    match kind {
+        BodyKind::BlackHole => SOLID_BLACK,
+        BodyKind::Star => WHITE7,
+        BodyKind::Gas => BLUE5,
+        BodyKind::DarkMatter => PURPLE3,
+        BodyKind::Tracer => GREEN5,
+        BodyKind::Custom(_) => YELLOW7,
+    }
+}
 
 /// Piston App for GUI
 pub struct App<'a> {
@@ -58,7 +72,7 @@ impl App<'_> {
             clear(BLACK, gl);
 
             // Plot all black holes
-            for i in 0..sim.config.num_blackholes {
+            for i in sim.blackholes() {
                 let transform = c
                     .transform
                     .trans(sim.r[i].x as f64, sim.r[i].y as f64)
@@ -71,12 +85,13 @@ impl App<'_> {
                 ellipse(SOLID_BLACK, blackhole_square, transform_center, gl);
             }
             // Plot all non-blackholes
-            for i in sim.config.num_blackholes..sim.n {
+            for i in 0..sim.n {
+                if sim.is_blackhole(i) { continue }
                 let transform = c
                     .transform
                     .trans(sim.r[i].x as f64, sim.r[i].y as f64)
                     .trans(-STAR_WIDTH / 2., -STAR_WIDTH / 2.);
-                ellipse(WHITE7, star_square, transform, gl);
+                ellipse(body_color(sim.kind[i]), star_square, transform, gl);
             }
         });
     }
@@ -84,7 +99,9 @@ impl App<'_> {
     /// Updates the simulation for one timestep.
     fn update(&mut self, _args: &UpdateArgs) {
         // nbody_direct(self.sim, 0.1);
-        nbody_barnes_hut(self.sim, 0.1, 1.);
+        if let Err(e) = nbody_barnes_hut(self.sim, 0.1, 1.) {
+            println!("Step failed: {}", e);
+        }
     }
 
     fn click(&mut self, mouse_xy: &[f64; 2]) {
@@ -106,7 +123,10 @@ fn main() {
     let min_dist: Scalar = 10.;
     let min_r: Vector3D = Vector3D::from_xy(0., 0.);
     let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
-    let config = NBodyConfig3D::new(min_dist, min_r, max_r);
+    let config = NBodyConfigBuilder::new(min_dist, min_r, max_r)
+        .blackhole_mergers(Some(BlackHoleMergerConfig::new(min_dist, 0.)))
+        .build()
+        .expect("Invalid simulation config");
     let mut sim: NBodySimulation3D = NBodySimulation3D::empty(1000, config);
     
     // Center of galaxy.
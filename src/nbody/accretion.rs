@@ -0,0 +1,87 @@
+//! Accretion of bodies onto black holes
+use super::{NBodySimulation};
+use super::bodies::{BodyKind};
+use super::collisions::{combine};
+use super::events::{Event};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Record of a black hole absorbing the body with id `absorbed`.
+#[derive(Debug, Clone, Copy)]
+pub struct AccretionRecord<F: Float = Scalar> {
+    pub t: F,
+    pub absorbed: u64,
+    pub m_accreted: F,
+    pub m_total: F,
+}
+
+/// Absorbs every body other than black holes, tracers and constrained bodies within
+/// `config.accretion_radius` of a black hole. The nearest black hole gains the body's mass
+/// and momentum, and records it in its accretion history.
+pub fn accrete<V: Vector>(sim: &mut NBodySimulation<V>) {
+    let radius_sqrd: V::F = sim.config.accretion_radius * sim.config.accretion_radius;
+    let mut absorbed: Vec<usize> = Vec::new();
+    let blackholes = sim.blackholes();
+
+    for i in 0..sim.n {
+        if sim.is_blackhole(i) || sim.kind[i] == BodyKind::Tracer || sim.is_constrained(i) { continue }
+
+        // Find the nearest black hole within the accretion radius
+        let mut nearest: Option<(usize, V::F)> = None;
+        for &ci in &blackholes {
+            let d_sqrd: V::F = (sim.r[ci] - sim.r[i]).l2_sqrd();
+            if d_sqrd < radius_sqrd && nearest.is_none_or(|(_, best)| d_sqrd < best) {
+                nearest = Some((ci, d_sqrd));
+            }
+        }
+
+        if let Some((ci, _)) = nearest {
+            let m_accreted: V::F = sim.m[i];
+            combine(sim, ci, i);
+            let record = AccretionRecord {
+                t: sim.t,
+                absorbed: sim.id[i],
+                m_accreted,
+                m_total: sim.m[ci],
+            };
+            sim.accretion_history[ci].push(record);
+            sim.emit(Event::Accretion { blackhole: sim.id[ci], record });
+            absorbed.push(i);
+        }
+    }
+
+    // Remove from the back so pending indices are not moved by earlier removals
+    for i in absorbed.into_iter().rev() {
+        sim.remove_body(i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, CollisionMode};
+
+    #[test]
+    fn test_accrete() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.collision_mode = CollisionMode::Accrete;
+        config.accretion_radius = 20.;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(3, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::zero(), m: 100. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(115., 100.), v: Vector3D::from_xy(0., 100.), m: 1. });
+        sim.set(2, &MovingBody3D { r: Vector3D::from_xy(150., 100.), v: Vector3D::zero(), m: 1. });
+        sim.kind[0] = BodyKind::BlackHole;
+
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert_eq!(sim.m[0], 101.);
+        assert!((sim.v[0] * sim.m[0] - Vector3D::from_xy(0., 100.)).l2_sqrd() < 1e-6);
+        assert_eq!(sim.r[1], Vector3D::from_xy(150., 100.));
+
+        assert_eq!(sim.accretion_history[0].len(), 1);
+        assert_eq!(sim.accretion_history[0][0].absorbed, 1);
+        assert_eq!(sim.accretion_history[0][0].m_accreted, 1.);
+        assert_eq!(sim.accreted_mass(0), 1.);
+    }
+}
@@ -1,52 +1,76 @@
 //! Barnes hut algorithm
-use super::{NBodySimulation3D};
-use crate::vector::{Scalar, Vector, Vector3D};
+use super::{NBodySimulation};
+use super::error::{NBodyError};
+use super::solver::{BarnesHutSolver, nbody_step};
+use super::timestep::{AdaptiveTimestep};
+use crate::vector::{Float, Vector};
 use crate::quadtree::{BoundingBox2D, MassQuadtree, MassQuadtreeIterator};
 
 /// Runs a single timestep of the simulation using the Barnes-Hut algorithm.
-pub fn nbody_barnes_hut(sim: &mut NBodySimulation3D, dt: Scalar, theta: Scalar) {
+pub fn nbody_barnes_hut<V: Vector>(sim: &mut NBodySimulation<V>, dt: V::F, theta: V::F) -> Result<(), NBodyError> {
+    nbody_step(sim, &BarnesHutSolver::new(theta), dt)
+}
+
+/// Runs a single timestep of the simulation using the Barnes-Hut algorithm,
+/// choosing the timestep from the accelerations. Returns the chosen timestep.
+pub fn nbody_barnes_hut_adaptive<V: Vector>(sim: &mut NBodySimulation<V>, timestep: &AdaptiveTimestep<V::F>, theta: V::F) -> Result<V::F, NBodyError> {
+    sim.begin_step();
+    compute_acc_barnes_hut(sim, theta);
+    let dt: V::F = timestep.choose(sim);
+    sim.integrate(dt)?;
+    sim.end_step();
+    Ok(dt)
+}
+
+/// Builds a quadtree of all sources over the simulation bounds, returning it with its bounding box.
+/// Tracers receive accelerations but are not sources of gravity, so they are left out.
+pub fn build_source_tree<V: Vector>(sim: &mut NBodySimulation<V>) -> (MassQuadtree<V::F>, BoundingBox2D<V::F>) {
     let (min_x, min_y) = sim.config.min_r.to_xy();
     let (max_x, max_y) = sim.config.max_r.to_xy();
-    let bb: BoundingBox2D = BoundingBox2D { min_x, max_x, min_y, max_y, };
-    let quadtree: MassQuadtree = MassQuadtree::new(&sim.r, &sim.m, bb);
+    let bb: BoundingBox2D<V::F> = BoundingBox2D { min_x, max_x, min_y, max_y, };
+    let quadtree: MassQuadtree<V::F> = sim.with_sources(|_, sources| MassQuadtree::new(&sources.r, &sources.m, bb));
+    (quadtree, bb)
+}
+
+/// Computes the acceleration of every body using the Barnes-Hut algorithm.
+/// The tree is built over the x and y components, so any z offsets are ignored.
+/// Results are stored in `sim.a`.
+pub fn compute_acc_barnes_hut<V: Vector>(sim: &mut NBodySimulation<V>, theta: V::F) {
+    let (quadtree, bb) = build_source_tree(sim);
     // println!("\n\nQuadtree: {:?}", quadtree);
 
     // For each point
     for i in 0..sim.n {
-        sim.a[i] = Vector3D::zero();
+        sim.a[i] = V::zero();
         // println!("r[i] = ({}, {})", sim.rx[i], sim.ry[i]);
 
+        let (x, y) = sim.r[i].to_xy();
         let quadtree_iter =
-            MassQuadtreeIterator::new(sim.r[i].x, sim.r[i].y, theta, &quadtree, bb);
+            MassQuadtreeIterator::new(x, y, theta, &quadtree, bb);
 
         // Get all points that are close enough to treat as individuals
         for node in quadtree_iter {
-            let d = Vector3D {
-                x: node.x - sim.r[i].x,
-                y: node.y - sim.r[i].y,
-                z: 0.,
-            };
-            let d_sqrd: Scalar = d.l2_sqrd();
+            let d: V = V::from_xy(node.x - x, node.y - y);
+            let d_sqrd: V::F = d.l2_sqrd();
             if d_sqrd < sim.config.min_dist_sqrd {
                 continue;
             }
 
             // if i == 0 { println!("Node: ({}, {}, {})", node.x, node.y, node.m); }
 
-            let inv_d_cubed: Scalar = 1. / d_sqrd.powf(3.);
+            let inv_d_sqrd: V::F = V::F::ONE / d_sqrd;
+            let inv_d_cubed: V::F = inv_d_sqrd * inv_d_sqrd * inv_d_sqrd;
             sim.a[i] += d * node.m * inv_d_cubed;
         }
         // if i == 0 { println!(); }
     }
-
-    sim.integrate(dt);
 }
 
 #[cfg(test)]
 mod test {
-    use crate::vector::{Scalar, Vector, Vector3D};
-    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, generate_galaxy};
-    use super::{nbody_barnes_hut};
+    use crate::vector::{Scalar, Vector, Vector2D, Vector3D};
+    use crate::nbody::{NBodyConfig2D, NBodyConfig3D, NBodySimulation2D, NBodySimulation3D, MovingBody2D, MovingBody3D, BodyKind, generate_galaxy};
+    use super::{nbody_barnes_hut, compute_acc_barnes_hut};
 
     #[test]
     fn test_barnes_hut() {
@@ -64,6 +88,47 @@ mod test {
             m: 5e6,
         };
         generate_galaxy(&mut sim, &c);
-        nbody_barnes_hut(&mut sim, 0.1, 2.);
+        nbody_barnes_hut(&mut sim, 0.1, 2.).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_barnes_hut_tracers() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(0, config);
+        sim.add_blackhole(&MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.add_body(&MovingBody3D { r: Vector3D::from_xy(300., 250.), v: Vector3D::zero(), m: 1. }, BodyKind::Star);
+        sim.add_body(&MovingBody3D { r: Vector3D::from_xy(250., 300.), v: Vector3D::zero(), m: 1e6 }, BodyKind::Tracer);
+
+        // The tracer feels the black hole but nothing feels the tracer
+        compute_acc_barnes_hut(&mut sim, 0.5);
+        assert_eq!(sim.a[1].y, 0.);
+        assert!(sim.a[2].y < 0.);
+    }
+
+    #[test]
+    fn test_barnes_hut_2d() {
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(500., 500.,);
+        let config = NBodyConfig2D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(3, config);
+        sim.set(0, &MovingBody2D { r: Vector2D::from_xy(250., 250.), v: Vector2D::from_xy(0., -0.01), m: 5e6 });
+        sim.set(1, &MovingBody2D { r: Vector2D::from_xy(300., 250.), v: Vector2D::from_xy(0., 2.), m: 1e3 });
+        sim.set(2, &MovingBody2D { r: Vector2D::from_xy(250., 180.), v: Vector2D::from_xy(1.5, 0.), m: 1e3 });
+
+        // In 2D the cross product is a scalar, the only component of the angular momentum
+        let l = |sim: &NBodySimulation2D| -> Scalar {
+            (0..sim.n).map(|i| sim.r[i].cross(sim.v[i]) * sim.m[i]).sum()
+        };
+        let l0: Scalar = l(&sim);
+
+        // With theta = 0 the tree sums exact pair forces, which are central and apply no torque
+        for _ in 0..10 {
+            nbody_barnes_hut(&mut sim, 0.1, 0.).unwrap();
+        }
+        assert_eq!(sim.step, 10);
+        assert!((l(&sim) - l0).abs() < 1e-4 * l0.abs(), "{} != {}", l(&sim), l0);
+        assert!(sim.r[1] != Vector2D::from_xy(300., 250.));
+    }
+}
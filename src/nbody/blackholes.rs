@@ -0,0 +1,126 @@
+//! Mergers between pairs of black holes
+use rand_distr::{Uniform, Distribution};
+use std::f64::{consts::PI};
+use super::{NBodySimulation};
+use super::collisions::{MergerEvent, combine};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Configures mergers between black holes.
+/// Black holes closer than `radius` merge, and the merged black hole receives a
+/// gravitational wave recoil kick of `recoil_speed` in a random direction.
+#[derive(Debug, Clone, Copy)]
+pub struct BlackHoleMergerConfig<F: Float = Scalar> {
+    pub radius: F,
+    pub recoil_speed: F,
+}
+
+impl<F: Float> BlackHoleMergerConfig<F> {
+    pub fn new(radius: F, recoil_speed: F) -> Self {
+        Self {
+            radius,
+            recoil_speed,
+        }
+    }
+}
+
+/// Merges all pairs of black holes within the merger radius, conserving mass and momentum
+/// before the recoil kick. Each merger is logged in `sim.mergers`.
+pub fn merge_blackholes<V: Vector>(sim: &mut NBodySimulation<V>, config: &BlackHoleMergerConfig<V::F>) {
+    let uniform: Uniform<f64> = Uniform::new(0., 2. * PI);
+    let radius_sqrd: V::F = config.radius * config.radius;
+    let mut merged: Vec<bool> = vec![false; sim.n];
+    let mut absorbed: Vec<usize> = Vec::new();
+    let blackholes = sim.blackholes();
+
+    for (idx, &i) in blackholes.iter().enumerate() {
+        for &k in &blackholes[idx + 1..] {
+            if merged[i] || merged[k] { continue }
+            if (sim.r[k] - sim.r[i]).l2_sqrd() >= radius_sqrd { continue }
+
+            // A constrained black hole survives, otherwise the heavier one.
+            // The survivor inherits the accretion history.
+            let (survivor, other) = if sim.is_constrained(i) != sim.is_constrained(k) {
+                if sim.is_constrained(i) { (i, k) } else { (k, i) }
+            } else if sim.m[i] >= sim.m[k] {
+                (i, k)
+            } else {
+                (k, i)
+            };
+            let (r_survivor, r_absorbed) = (sim.r[survivor], sim.r[other]);
+            combine(sim, survivor, other);
+            let mut history = std::mem::take(&mut sim.accretion_history[other]);
+            sim.accretion_history[survivor].append(&mut history);
+            sim.accretion_history[survivor].sort_by(|a, b| a.t.total_cmp(&b.t));
+
+            // Constrained black holes keep their trajectory, so they take no recoil
+            let theta: V::F = V::F::from_f64(uniform.sample(&mut sim.rng));
+            let recoil = if sim.is_constrained(survivor) {
+                V::zero()
+            } else {
+                V::from_xy(theta.cos(), theta.sin()) * config.recoil_speed
+            };
+            sim.v[survivor] += recoil;
+
+            sim.log_merger(MergerEvent {
+                t: sim.t,
+                survivor: sim.id[survivor],
+                absorbed: sim.id[other],
+                r_survivor,
+                r_absorbed,
+                m: sim.m[survivor],
+                r: sim.r[survivor],
+                recoil,
+            });
+            merged[i] = true;
+            merged[k] = true;
+            absorbed.push(other);
+        }
+    }
+
+    // Remove from the back so pending indices are not moved by earlier removals
+    absorbed.sort_unstable_by(|a, b| b.cmp(a));
+    for i in absorbed {
+        sim.remove_body(i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, BlackHoleMergerConfig};
+
+    #[test]
+    fn test_merge_blackholes() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let setup = |blackhole_mergers| {
+            let mut config = NBodyConfig3D::new(10., min_r, max_r);
+            config.blackhole_mergers = blackhole_mergers;
+            let mut sim: NBodySimulation3D = NBodySimulation3D::empty(3, config);
+            sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::from_xy(1., 0.), m: 3e6 });
+            sim.set(1, &MovingBody3D { r: Vector3D::from_xy(115., 100.), v: Vector3D::from_xy(-1., 0.), m: 1e6 });
+            sim.set(2, &MovingBody3D { r: Vector3D::from_xy(300., 300.), v: Vector3D::zero(), m: 1. });
+            sim.kind[0] = BodyKind::BlackHole;
+            sim.kind[1] = BodyKind::BlackHole;
+            sim
+        };
+
+        // Mergers are off unless configured
+        let mut sim: NBodySimulation3D = setup(NBodyConfig3D::new(10., min_r, max_r).blackhole_mergers);
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.num_blackholes(), 2);
+
+        let mut sim: NBodySimulation3D = setup(Some(BlackHoleMergerConfig::new(20., 3.)));
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert_eq!(sim.num_blackholes(), 1);
+        assert_eq!(sim.m[0], 4e6);
+        assert_eq!(sim.r[1], Vector3D::from_xy(300., 300.));
+
+        // Momentum is conserved up to the recoil kick
+        assert_eq!(sim.mergers.len(), 1);
+        let recoil = sim.mergers[0].recoil;
+        assert!((recoil.l2_sqrd().sqrt() - 3.).abs() < 1e-4);
+        assert!((sim.v[0] - recoil - Vector3D::from_xy(0.5, 0.)).l2_sqrd() < 1e-8);
+    }
+}
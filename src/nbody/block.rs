@@ -0,0 +1,197 @@
+//! Hierarchical block timesteps for the Hermite integrator
+use super::{NBodySimulation};
+use super::direct::{acc_jerk_direct, compute_acc_jerk_direct};
+use super::error::{NBodyError};
+use super::finite::{snapshot, check_finite};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Deepest level allowed, so tick counts stay exact in a `u64` and an `f64`.
+pub const MAX_BLOCK_LEVEL: usize = 32;
+
+/// Configures hierarchical block timesteps.
+///
+/// Each body is assigned a level `k` and advances with timestep `dt_max / 2^k`,
+/// where `k` is chosen from the criterion `dt = eta * |a| / |j|`.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockTimestepConfig<F: Float = Scalar> {
+    pub dt_max: F,
+    pub max_level: usize,
+    pub eta: F,
+}
+
+impl<F: Float> BlockTimestepConfig<F> {
+    /// Constructs a config, checking its values with `validate`.
+    pub fn new(dt_max: F, max_level: usize, eta: F) -> Result<Self, NBodyError> {
+        let config = Self {
+            dt_max,
+            max_level,
+            eta,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that `dt_max` and `eta` are finite and positive,
+    /// and that `max_level` is at most `MAX_BLOCK_LEVEL`.
+    pub fn validate(&self) -> Result<(), NBodyError> {
+        if !self.dt_max.is_finite() || self.dt_max <= F::ZERO {
+            return Err(NBodyError::InvalidMaxTimestep(self.dt_max.to_f64()));
+        }
+        if !self.eta.is_finite() || self.eta <= F::ZERO {
+            return Err(NBodyError::InvalidEta(self.eta.to_f64()));
+        }
+        if self.max_level > MAX_BLOCK_LEVEL {
+            return Err(NBodyError::InvalidMaxLevel(self.max_level));
+        }
+        Ok(())
+    }
+
+    /// Gets the smallest timestep allowed.
+    pub fn dt_min(&self) -> F {
+        self.dt_max / F::from_f64((1u64 << self.max_level) as f64)
+    }
+
+    /// Gets the level whose timestep satisfies the accuracy criterion for a body.
+    pub fn level<V: Vector<F = F>>(&self, a: V, j: V) -> usize {
+        let j_norm: F = j.l2_sqrd().sqrt();
+        if j_norm == F::ZERO { return 0 }
+
+        let dt: F = self.eta * a.l2_sqrd().sqrt() / j_norm;
+        let mut level: usize = 0;
+        while level < self.max_level && self.dt_max / F::from_f64((1u64 << level) as f64) > dt {
+            level += 1;
+        }
+        level
+    }
+}
+
+/// Summary of a single block step.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockStepStats {
+    pub substeps: usize,
+    pub force_evaluations: usize,
+}
+
+/// Advances the simulation by `config.dt_max` using Hermite block timesteps.
+///
+/// Time is divided into ticks of `config.dt_min()`. At every substep only the active bodies,
+/// those whose own step ends on the current tick, have their forces recomputed.
+/// All other bodies contribute through their predicted positions.
+/// All bodies are synchronized again at the end of the block.
+pub fn nbody_block_hermite<V: Vector>(sim: &mut NBodySimulation<V>, config: &BlockTimestepConfig<V::F>) -> Result<BlockStepStats, NBodyError> {
+    config.validate()?;
+    sim.begin_step();
+    let snapshot = snapshot(sim);
+    let mut stats = BlockStepStats::default();
+    let dt_min: V::F = config.dt_min();
+    let ticks_to_time = |ticks: u64| -> V::F { V::F::from_f64(ticks as f64) * dt_min };
+    let (two, six, twelve) = (V::F::from_f64(2.), V::F::from_f64(6.), V::F::from_f64(12.));
+    let total_ticks: u64 = 1 << config.max_level;
+    let ticks = |level: usize| -> u64 { 1 << (config.max_level - level) };
+
+    compute_acc_jerk_direct(sim);
+    stats.force_evaluations += sim.n;
+    let mut level: Vec<usize> = (0..sim.n).map(|i| config.level(sim.a[i], sim.j[i])).collect();
+    let mut t_last: Vec<u64> = vec![0; sim.n];
+    let mut rp: Vec<V> = sim.r.clone();
+    let mut vp: Vec<V> = sim.v.clone();
+    let sources: Vec<usize> = sim.sources();
+
+    let mut tick: u64 = 0;
+    while tick < total_ticks {
+        // The next substep ends when the earliest body finishes its step
+        tick = (0..sim.n).map(|i| t_last[i] + ticks(level[i])).min().unwrap_or(total_ticks);
+        let active: Vec<usize> = (0..sim.n).filter(|&i| t_last[i] + ticks(level[i]) == tick).collect();
+
+        // Predict every body to the current tick
+        let t: V::F = sim.t + ticks_to_time(tick);
+        for i in 0..sim.n {
+            if let Some((r, v)) = sim.constrained_state(i, t) {
+                rp[i] = r;
+                vp[i] = v;
+                continue;
+            }
+            let dt: V::F = ticks_to_time(tick - t_last[i]);
+            rp[i] = sim.r[i] + sim.v[i] * dt + sim.a[i] * (dt * dt / two) + sim.j[i] * (dt * dt * dt / six);
+            vp[i] = sim.v[i] + sim.a[i] * dt + sim.j[i] * (dt * dt / two);
+        }
+
+        // Evaluate and correct only the active bodies
+        let updates: Vec<(V, V)> = active.iter()
+            .map(|&i| acc_jerk_direct(&rp, &vp, &sim.m, &sources, sim.config.min_dist_sqrd, i))
+            .collect();
+        for (&i, &(a1, j1)) in active.iter().zip(updates.iter()) {
+            if let Some((r, v)) = sim.constrained_state(i, t) {
+                sim.r[i] = r;
+                sim.v[i] = v;
+                t_last[i] = tick;
+                continue;
+            }
+            let dt: V::F = ticks_to_time(tick - t_last[i]);
+            let dt12: V::F = dt * dt / twelve;
+            let v1 = sim.v[i] + (sim.a[i] + a1) * (dt / two) + (sim.j[i] - j1) * dt12;
+            sim.r[i] += (sim.v[i] + v1) * (dt / two) + (sim.a[i] - a1) * dt12;
+            sim.v[i] = v1;
+            sim.a[i] = a1;
+            sim.j[i] = j1;
+            t_last[i] = tick;
+
+            // Bodies may always move to a finer level, but only move to a coarser
+            // level when the current tick is aligned with the coarser step.
+            let desired = config.level(a1, j1);
+            if desired > level[i] {
+                level[i] = desired;
+            } else if desired < level[i] && tick.is_multiple_of(ticks(level[i] - 1)) {
+                level[i] -= 1;
+            }
+        }
+
+        stats.substeps += 1;
+        stats.force_evaluations += active.len();
+    }
+
+    sim.t += config.dt_max;
+    check_finite(sim, snapshot)?;
+    sim.resolve_encounters();
+    sim.end_step();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, NBodyError};
+    use super::{nbody_block_hermite, BlockTimestepConfig};
+
+    #[test]
+    fn test_block_hermite() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(3, config);
+
+        // One satellite in a tight orbit and one far away.
+        let m: Scalar = 5e6;
+        let s = |radius: Scalar| m.sqrt() / radius / radius;
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(280., 250.), v: Vector3D::from_xy(0., s(30.)), m: 1e-2 });
+        sim.set(2, &MovingBody3D { r: Vector3D::from_xy(250., 450.), v: Vector3D::from_xy(s(200.), 0.), m: 1e-2 });
+        sim.kind[0] = BodyKind::BlackHole;
+
+        assert_eq!(BlockTimestepConfig::<Scalar>::new(1., 64, 0.01).unwrap_err(), NBodyError::InvalidMaxLevel(64));
+        assert_eq!(BlockTimestepConfig::<Scalar>::new(0., 4, 0.01).unwrap_err(), NBodyError::InvalidMaxTimestep(0.));
+        assert_eq!(BlockTimestepConfig::<Scalar>::new(-1., 4, 0.01).unwrap_err(), NBodyError::InvalidMaxTimestep(-1.));
+        assert!(BlockTimestepConfig::<Scalar>::new(Scalar::INFINITY, 4, 0.01).is_err());
+        assert_eq!(BlockTimestepConfig::<Scalar>::new(1., 4, 0.).unwrap_err(), NBodyError::InvalidEta(0.));
+        assert!(BlockTimestepConfig::<Scalar>::new(1., 4, Scalar::NAN).is_err());
+        let block_config = BlockTimestepConfig::new(1., 4, 0.01).unwrap();
+        let stats = nbody_block_hermite(&mut sim, &block_config).unwrap();
+
+        // The far satellite should be updated less often than the close one.
+        assert!(stats.substeps > 1);
+        assert!(stats.force_evaluations < (stats.substeps + 1) * sim.n);
+
+        let d = (sim.r[1] - sim.r[0]).l2_sqrd().sqrt();
+        assert!((d - 30.).abs() < 1e-2, "radius drifted to {}", d);
+    }
+}
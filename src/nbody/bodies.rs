@@ -1,17 +1,29 @@
 // Generic body definitions.
-pub use crate::vector::{Scalar, Vector, Vector3D};
+pub use crate::vector::{Scalar, Vector, Vector2D, Vector3D};
 
 #[derive(Debug)]
 pub struct Body<V: Vector> {
-    pub m: Scalar,
+    pub m: V::F,
     pub r: V,
 }
 
 #[derive(Debug)]
 pub struct MovingBody<V: Vector> {
-    pub m: Scalar,
+    pub m: V::F,
     pub r: V,
     pub v: V,
 }
 
+pub type MovingBody2D = MovingBody<Vector2D>;
 pub type MovingBody3D = MovingBody<Vector3D>;
+
+/// Kind of a body, which drives how it is generated, drawn and how it collides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BodyKind {
+    BlackHole,
+    Star,
+    Gas,
+    DarkMatter,
+    Tracer,
+    Custom(u32),
+}
@@ -0,0 +1,152 @@
+//! Handling of bodies that leave the simulation bounds
+use rand::Rng;
+use super::{NBodySimulation};
+use super::events::{Event};
+use crate::vector::{Float, Vector};
+
+/// What happens to a body that leaves the box between `config.min_r` and `config.max_r`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryPolicy {
+    /// Bodies may leave the bounds and keep moving.
+    Open,
+    /// Bodies that leave the bounds are removed from the simulation.
+    Remove,
+    /// Bodies bounce off the walls of the box.
+    Reflect,
+    /// Bodies wrap around to the opposite side of the box.
+    Periodic,
+    /// Bodies are replaced by a new satellite of a random black hole inside the bounds.
+    /// Behaves like `Open` while there are no such black holes.
+    Respawn,
+}
+
+/// Applies the configured boundary policy to all bodies that are out of bounds.
+/// Constrained bodies keep their trajectory, so they are reported but not moved or removed.
+pub fn apply_boundary<V: Vector>(sim: &mut NBodySimulation<V>) {
+    let min_r = sim.config.min_r;
+    let max_r = sim.config.max_r;
+
+    // Respawned stars are placed around black holes that are still in bounds,
+    // so a body that left the box is never a candidate for itself
+    let mut blackholes: Vec<usize> = match sim.config.boundary {
+        BoundaryPolicy::Respawn => sim.blackholes().into_iter()
+            .filter(|&k| sim.r[k].in_bounds(&min_r, &max_r))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    // Iterate backwards so removals only move bodies that were already checked
+    for i in (0..sim.n).rev() {
+        if sim.r[i].in_bounds(&min_r, &max_r) { continue }
+        sim.emit(Event::OutOfBounds { id: sim.id[i], r: sim.r[i] });
+        if sim.is_constrained(i) { continue }
+
+        match sim.config.boundary {
+            BoundaryPolicy::Open => (),
+            BoundaryPolicy::Remove => sim.remove_body(i),
+            BoundaryPolicy::Reflect => {
+                for k in 0..V::DIM {
+                    let (lo, hi) = (min_r.component(k), max_r.component(k));
+                    let x: V::F = sim.r[i].component(k);
+                    if x < lo || x > hi {
+                        let x = if x < lo { lo + lo - x } else { hi + hi - x };
+                        sim.r[i].set_component(k, x.max(lo).min(hi));
+                        let vx: V::F = sim.v[i].component(k);
+                        sim.v[i].set_component(k, -vx);
+                    }
+                }
+            },
+            BoundaryPolicy::Periodic => {
+                for k in 0..V::DIM {
+                    let (lo, hi) = (min_r.component(k), max_r.component(k));
+                    let width: V::F = hi - lo;
+                    if width > V::F::ZERO {
+                        let x: V::F = sim.r[i].component(k);
+                        sim.r[i].set_component(k, lo + (x - lo).rem_euclid(width));
+                    }
+                }
+            },
+            BoundaryPolicy::Respawn => {
+                // Don't reset if there are no black holes in bounds
+                if blackholes.is_empty() { continue }
+
+                // Pick a random black hole to put next to. A black hole that is reset becomes a star.
+                let ci = blackholes[sim.rng.gen_range(0, blackholes.len())];
+                sim.reset(i, ci);
+                blackholes.retain(|&k| k != i);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, Motion, Event};
+    use super::{BoundaryPolicy};
+
+    fn escaping_sim(boundary: BoundaryPolicy) -> NBodySimulation3D {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.boundary = boundary;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(495., 100.), v: Vector3D::from_xy(10., 0.), m: 1. });
+        sim.kind[0] = BodyKind::BlackHole;
+        sim
+    }
+
+    #[test]
+    fn test_boundary_policies() {
+        // Open bounds still report bodies that leave
+        let mut sim = escaping_sim(BoundaryPolicy::Open);
+        let left = Rc::new(Cell::new(0));
+        let count = left.clone();
+        sim.add_observer(move |_: &NBodySimulation3D, event: &Event<Vector3D>| {
+            if let Event::OutOfBounds { .. } = event { count.set(count.get() + 1) }
+        });
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.r[1], Vector3D::from_xy(505., 100.));
+        assert_eq!(left.get(), 1);
+
+        let mut sim = escaping_sim(BoundaryPolicy::Remove);
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.n, 1);
+
+        let mut sim = escaping_sim(BoundaryPolicy::Reflect);
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.r[1], Vector3D::from_xy(495., 100.));
+        assert_eq!(sim.v[1], Vector3D::from_xy(-10., 0.));
+
+        let mut sim = escaping_sim(BoundaryPolicy::Periodic);
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.r[1], Vector3D::from_xy(5., 100.));
+
+        let mut sim = escaping_sim(BoundaryPolicy::Respawn);
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert!(sim.r[1].in_bounds(&sim.config.min_r, &sim.config.max_r));
+
+        // With its only black hole out of bounds, a body is left in place instead of respawning around it
+        let mut sim = escaping_sim(BoundaryPolicy::Respawn);
+        sim.r[0] = Vector3D::from_xy(600., 250.);
+        sim.r[1] = Vector3D::from_xy(505., 100.);
+        sim.v[1] = Vector3D::zero();
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert!(sim.is_blackhole(0));
+        assert!(!sim.r[0].in_bounds(&sim.config.min_r, &sim.config.max_r));
+        assert!(!sim.r[1].in_bounds(&sim.config.min_r, &sim.config.max_r));
+
+        // Constrained bodies are left on their trajectory
+        let mut sim = escaping_sim(BoundaryPolicy::Remove);
+        sim.r[1] = Vector3D::from_xy(505., 100.);
+        sim.motion[1] = Motion::Pinned;
+        sim.integrate(1.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert_eq!(sim.r[1], Vector3D::from_xy(505., 100.));
+    }
+}
@@ -0,0 +1,183 @@
+//! Validated builders for simulation configs and simulations
+use super::{NBodyConfig, NBodySimulation};
+use super::bodies::{MovingBody};
+use super::blackholes::{BlackHoleMergerConfig};
+use super::boundary::{BoundaryPolicy};
+use super::collisions::{CollisionMode};
+use super::error::{NBodyError};
+use super::finite::{NonFinitePolicy};
+use crate::vector::{Float, Vector};
+
+/// Checks that a value is a finite, non-negative length or speed.
+fn non_negative<F: Float>(x: F) -> bool {
+    x.is_finite() && x >= F::ZERO
+}
+
+impl<V: Vector> NBodyConfig<V> {
+    /// Checks every setting of the config, returning the first invalid one.
+    pub fn validate(&self) -> Result<(), NBodyError> {
+        // The self-pair is only skipped by min_dist, so it must be positive
+        if !non_negative(self.min_dist) || self.min_dist == V::F::ZERO {
+            return Err(NBodyError::InvalidMinDist(self.min_dist.to_f64()));
+        }
+        for axis in 0..V::DIM {
+            let (min, max) = (self.min_r.component(axis), self.max_r.component(axis));
+            if min.is_nan() || max.is_nan() || min > max {
+                return Err(NBodyError::InvertedBounds { axis, min: min.to_f64(), max: max.to_f64() });
+            }
+        }
+        if !non_negative(self.accretion_radius) {
+            return Err(NBodyError::InvalidAccretionRadius(self.accretion_radius.to_f64()));
+        }
+        if let Some(mergers) = self.blackhole_mergers {
+            if !non_negative(mergers.radius) {
+                return Err(NBodyError::InvalidMergerRadius(mergers.radius.to_f64()));
+            }
+            if !non_negative(mergers.recoil_speed) {
+                return Err(NBodyError::InvalidRecoilSpeed(mergers.recoil_speed.to_f64()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builder for a validated `NBodyConfig`.
+#[derive(Debug)]
+pub struct NBodyConfigBuilder<V: Vector> {
+    config: NBodyConfig<V>,
+}
+
+impl<V: Vector> NBodyConfigBuilder<V> {
+    /// Starts from the defaults of `NBodyConfig::new`.
+    pub fn new(min_dist: V::F, min_r: V, max_r: V) -> Self {
+        Self {
+            config: NBodyConfig::new(min_dist, min_r, max_r),
+        }
+    }
+
+    pub fn collision_mode(mut self, collision_mode: CollisionMode) -> Self {
+        self.config.collision_mode = collision_mode;
+        self
+    }
+
+    pub fn accretion_radius(mut self, accretion_radius: V::F) -> Self {
+        self.config.accretion_radius = accretion_radius;
+        self
+    }
+
+    /// Enables mergers between black holes. They are disabled by default.
+    pub fn blackhole_mergers(mut self, blackhole_mergers: Option<BlackHoleMergerConfig<V::F>>) -> Self {
+        self.config.blackhole_mergers = blackhole_mergers;
+        self
+    }
+
+    pub fn boundary(mut self, boundary: BoundaryPolicy) -> Self {
+        self.config.boundary = boundary;
+        self
+    }
+
+    pub fn non_finite(mut self, non_finite: NonFinitePolicy) -> Self {
+        self.config.non_finite = non_finite;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Validates and returns the config.
+    pub fn build(self) -> Result<NBodyConfig<V>, NBodyError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Builder for a validated `NBodySimulation`.
+#[derive(Debug)]
+pub struct NBodySimulationBuilder<V: Vector> {
+    config: NBodyConfig<V>,
+    n: usize,
+    blackholes: Vec<MovingBody<V>>,
+}
+
+impl<V: Vector> NBodySimulationBuilder<V> {
+    pub fn new(config: NBodyConfig<V>) -> Self {
+        Self {
+            config,
+            n: 0,
+            blackholes: Vec::new(),
+        }
+    }
+
+    /// Sets the total number of bodies, including black holes.
+    pub fn bodies(mut self, n: usize) -> Self {
+        self.n = n;
+        self
+    }
+
+    /// Adds a black hole, which counts towards the total number of bodies.
+    pub fn blackhole(mut self, body: MovingBody<V>) -> Self {
+        self.blackholes.push(body);
+        self
+    }
+
+    /// Validates the config and returns the simulation.
+    pub fn build(self) -> Result<NBodySimulation<V>, NBodyError> {
+        self.config.validate()?;
+        if self.blackholes.len() > self.n {
+            return Err(NBodyError::TooManyBlackHoles { n: self.n, num_blackholes: self.blackholes.len() });
+        }
+
+        let mut sim = NBodySimulation::empty(self.n - self.blackholes.len(), self.config);
+        for body in &self.blackholes {
+            let i = sim.add_blackhole(body);
+            sim.radius[i] = sim.config.min_dist;
+        }
+        Ok(sim)
+    }
+}
+
+impl<V: Vector> NBodySimulation<V> {
+    /// Starts building a validated simulation.
+    pub fn builder(config: NBodyConfig<V>) -> NBodySimulationBuilder<V> {
+        NBodySimulationBuilder::new(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyError, NBodyConfig3D, NBodySimulation3D, MovingBody3D};
+    use super::{NBodyConfigBuilder};
+
+    #[test]
+    fn test_builder() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+
+        assert_eq!(
+            NBodyConfigBuilder::new(-1., min_r, max_r).build().unwrap_err(),
+            NBodyError::InvalidMinDist(-1.));
+        assert_eq!(
+            NBodyConfigBuilder::new(0., min_r, max_r).build().unwrap_err(),
+            NBodyError::InvalidMinDist(0.));
+        assert_eq!(
+            NBodyConfigBuilder::new(10., max_r, min_r).build().unwrap_err(),
+            NBodyError::InvertedBounds { axis: 0, min: 500., max: 0. });
+        assert_eq!(
+            NBodyConfigBuilder::new(10., min_r, max_r).accretion_radius(-2.).build().unwrap_err(),
+            NBodyError::InvalidAccretionRadius(-2.));
+
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).build().unwrap();
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        let too_small = NBodySimulation3D::builder(config).bodies(0).blackhole(c).build();
+        assert_eq!(too_small.unwrap_err(), NBodyError::TooManyBlackHoles { n: 0, num_blackholes: 1 });
+
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        let sim = NBodySimulation3D::builder(config).bodies(10).blackhole(c).build().unwrap();
+        assert_eq!(sim.n, 10);
+        assert_eq!(sim.num_blackholes(), 1);
+    }
+}
@@ -0,0 +1,483 @@
+//! Versioned binary checkpoints of the full simulation state
+//!
+//! All values are stored little-endian, with scalars stored as `f64` regardless of the
+//! precision of the simulation. A checkpoint holds the config, time, step count, RNG state,
+//! every body with its kind, id, motion and accretion history, and the merger and
+//! non-finite logs. Observers are not saved and must be registered again after loading.
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path as FilePath;
+use super::{NBodyConfig, NBodySimulation};
+use super::accretion::{AccretionRecord};
+use super::blackholes::{BlackHoleMergerConfig};
+use super::bodies::{BodyKind, MovingBody};
+use super::boundary::{BoundaryPolicy};
+use super::collisions::{CollisionMode, MergerEvent};
+use super::error::{NBodyError};
+use super::finite::{NonFinitePolicy, NonFiniteRecord};
+use super::motion::{Motion, Path};
+use super::rng::{SimRng};
+use crate::vector::{Float, Vector};
+
+/// Identifies a checkpoint file.
+pub const CHECKPOINT_MAGIC: [u8; 8] = *b"NBODYCKP";
+
+/// Version written by `write_checkpoint`.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+impl From<io::Error> for NBodyError {
+    fn from(e: io::Error) -> Self {
+        NBodyError::Io(e.to_string())
+    }
+}
+
+/// Little-endian encoder for checkpoint values.
+struct Encoder<W: Write> {
+    w: W,
+}
+
+impl<W: Write> Encoder<W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), NBodyError> {
+        Ok(self.w.write_all(bytes)?)
+    }
+
+    fn u8(&mut self, x: u8) -> Result<(), NBodyError> {
+        self.bytes(&[x])
+    }
+
+    fn u32(&mut self, x: u32) -> Result<(), NBodyError> {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    fn u64(&mut self, x: u64) -> Result<(), NBodyError> {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    fn len(&mut self, x: usize) -> Result<(), NBodyError> {
+        self.u64(x as u64)
+    }
+
+    fn scalar<F: Float>(&mut self, x: F) -> Result<(), NBodyError> {
+        self.bytes(&x.to_f64().to_le_bytes())
+    }
+
+    fn vector<V: Vector>(&mut self, x: V) -> Result<(), NBodyError> {
+        for k in 0..V::DIM {
+            self.scalar(x.component(k))?;
+        }
+        Ok(())
+    }
+}
+
+/// Little-endian decoder for checkpoint values.
+struct Decoder<R: Read> {
+    r: R,
+}
+
+impl<R: Read> Decoder<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], NBodyError> {
+        let mut buf = [0u8; N];
+        self.r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> Result<u8, NBodyError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, NBodyError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, NBodyError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn len(&mut self) -> Result<usize, NBodyError> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn scalar<F: Float>(&mut self) -> Result<F, NBodyError> {
+        Ok(F::from_f64(f64::from_le_bytes(self.bytes()?)))
+    }
+
+    fn vector<V: Vector>(&mut self) -> Result<V, NBodyError> {
+        let mut x = V::zero();
+        for k in 0..V::DIM {
+            x.set_component(k, self.scalar()?);
+        }
+        Ok(x)
+    }
+
+    /// Reads a tag, failing if it is not below `count`.
+    fn tag(&mut self, what: &str, count: u8) -> Result<u8, NBodyError> {
+        let tag = self.u8()?;
+        if tag >= count {
+            return Err(NBodyError::InvalidCheckpoint(format!("unknown {} tag {}", what, tag)));
+        }
+        Ok(tag)
+    }
+}
+
+fn write_config<V: Vector, W: Write>(e: &mut Encoder<W>, config: &NBodyConfig<V>) -> Result<(), NBodyError> {
+    e.scalar(config.min_dist)?;
+    e.vector(config.min_r)?;
+    e.vector(config.max_r)?;
+    e.u8(match config.collision_mode {
+        CollisionMode::Respawn => 0,
+        CollisionMode::Merge => 1,
+        CollisionMode::Accrete => 2,
+    })?;
+    e.scalar(config.accretion_radius)?;
+    match config.blackhole_mergers {
+        None => e.u8(0)?,
+        Some(mergers) => {
+            e.u8(1)?;
+            e.scalar(mergers.radius)?;
+            e.scalar(mergers.recoil_speed)?;
+        },
+    }
+    e.u8(match config.boundary {
+        BoundaryPolicy::Open => 0,
+        BoundaryPolicy::Remove => 1,
+        BoundaryPolicy::Reflect => 2,
+        BoundaryPolicy::Periodic => 3,
+        BoundaryPolicy::Respawn => 4,
+    })?;
+    e.u8(match config.non_finite {
+        NonFinitePolicy::Error => 0,
+        NonFinitePolicy::Remove => 1,
+        NonFinitePolicy::Rollback => 2,
+    })?;
+    match config.seed {
+        None => e.u8(0),
+        Some(seed) => {
+            e.u8(1)?;
+            e.u64(seed)
+        },
+    }
+}
+
+fn read_config<V: Vector, R: Read>(d: &mut Decoder<R>) -> Result<NBodyConfig<V>, NBodyError> {
+    let min_dist = d.scalar()?;
+    let min_r = d.vector()?;
+    let max_r = d.vector()?;
+    let mut config = NBodyConfig::new(min_dist, min_r, max_r);
+    config.collision_mode = match d.tag("collision mode", 3)? {
+        0 => CollisionMode::Respawn,
+        1 => CollisionMode::Merge,
+        _ => CollisionMode::Accrete,
+    };
+    config.accretion_radius = d.scalar()?;
+    config.blackhole_mergers = match d.tag("merger config", 2)? {
+        0 => None,
+        _ => Some(BlackHoleMergerConfig::new(d.scalar()?, d.scalar()?)),
+    };
+    config.boundary = match d.tag("boundary policy", 5)? {
+        0 => BoundaryPolicy::Open,
+        1 => BoundaryPolicy::Remove,
+        2 => BoundaryPolicy::Reflect,
+        3 => BoundaryPolicy::Periodic,
+        _ => BoundaryPolicy::Respawn,
+    };
+    config.non_finite = match d.tag("non-finite policy", 3)? {
+        0 => NonFinitePolicy::Error,
+        1 => NonFinitePolicy::Remove,
+        _ => NonFinitePolicy::Rollback,
+    };
+    config.seed = match d.tag("seed", 2)? {
+        0 => None,
+        _ => Some(d.u64()?),
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+fn write_kind<W: Write>(e: &mut Encoder<W>, kind: BodyKind) -> Result<(), NBodyError> {
+    match kind {
+        BodyKind::BlackHole => e.u8(0),
+        BodyKind::Star => e.u8(1),
+        BodyKind::Gas => e.u8(2),
+        BodyKind::DarkMatter => e.u8(3),
+        BodyKind::Tracer => e.u8(4),
+        BodyKind::Custom(tag) => {
+            e.u8(5)?;
+            e.u32(tag)
+        },
+    }
+}
+
+fn read_kind<R: Read>(d: &mut Decoder<R>) -> Result<BodyKind, NBodyError> {
+    Ok(match d.tag("body kind", 6)? {
+        0 => BodyKind::BlackHole,
+        1 => BodyKind::Star,
+        2 => BodyKind::Gas,
+        3 => BodyKind::DarkMatter,
+        4 => BodyKind::Tracer,
+        _ => BodyKind::Custom(d.u32()?),
+    })
+}
+
+fn write_motion<V: Vector, W: Write>(e: &mut Encoder<W>, motion: &Motion<V>, id: u64) -> Result<(), NBodyError> {
+    match motion {
+        Motion::Free => e.u8(0),
+        Motion::Pinned => e.u8(1),
+        Motion::Scripted(Path::Circular { center, radius, omega, phase }) => {
+            e.u8(2)?;
+            e.vector(*center)?;
+            e.scalar(*radius)?;
+            e.scalar(*omega)?;
+            e.scalar(*phase)
+        },
+        Motion::Scripted(Path::Custom(_)) => Err(NBodyError::UnsavableMotion { id }),
+    }
+}
+
+fn read_motion<V: Vector, R: Read>(d: &mut Decoder<R>) -> Result<Motion<V>, NBodyError> {
+    Ok(match d.tag("motion", 3)? {
+        0 => Motion::Free,
+        1 => Motion::Pinned,
+        _ => Motion::Scripted(Path::Circular {
+            center: d.vector()?,
+            radius: d.scalar()?,
+            omega: d.scalar()?,
+            phase: d.scalar()?,
+        }),
+    })
+}
+
+fn write_merger<V: Vector, W: Write>(e: &mut Encoder<W>, event: &MergerEvent<V>) -> Result<(), NBodyError> {
+    e.scalar(event.t)?;
+    e.u64(event.survivor)?;
+    e.u64(event.absorbed)?;
+    e.vector(event.r_survivor)?;
+    e.vector(event.r_absorbed)?;
+    e.scalar(event.m)?;
+    e.vector(event.r)?;
+    e.vector(event.recoil)
+}
+
+fn read_merger<V: Vector, R: Read>(d: &mut Decoder<R>) -> Result<MergerEvent<V>, NBodyError> {
+    Ok(MergerEvent {
+        t: d.scalar()?,
+        survivor: d.u64()?,
+        absorbed: d.u64()?,
+        r_survivor: d.vector()?,
+        r_absorbed: d.vector()?,
+        m: d.scalar()?,
+        r: d.vector()?,
+        recoil: d.vector()?,
+    })
+}
+
+impl<V: Vector> NBodySimulation<V> {
+    /// Writes a checkpoint of the full simulation state.
+    /// Fails with `NBodyError::UnsavableMotion` if a body follows a custom path.
+    pub fn write_checkpoint<W: Write>(&self, w: W) -> Result<(), NBodyError> {
+        let mut e = Encoder { w };
+        e.bytes(&CHECKPOINT_MAGIC)?;
+        e.u32(CHECKPOINT_VERSION)?;
+        e.u32(V::DIM as u32)?;
+
+        write_config(&mut e, &self.config)?;
+        e.scalar(self.t)?;
+        e.u64(self.step)?;
+        e.u64(self.rng.state())?;
+        e.u64(self.next_id())?;
+
+        e.len(self.n)?;
+        for i in 0..self.n {
+            e.u64(self.id[i])?;
+            write_kind(&mut e, self.kind[i])?;
+            write_motion(&mut e, &self.motion[i], self.id[i])?;
+            e.scalar(self.m[i])?;
+            e.scalar(self.radius[i])?;
+            e.vector(self.r[i])?;
+            e.vector(self.v[i])?;
+            e.vector(self.a[i])?;
+            e.vector(self.j[i])?;
+            e.len(self.accretion_history[i].len())?;
+            for record in &self.accretion_history[i] {
+                e.scalar(record.t)?;
+                e.u64(record.absorbed)?;
+                e.scalar(record.m_accreted)?;
+                e.scalar(record.m_total)?;
+            }
+        }
+
+        e.len(self.mergers.len())?;
+        for event in &self.mergers {
+            write_merger(&mut e, event)?;
+        }
+        e.len(self.non_finite_log.len())?;
+        for record in &self.non_finite_log {
+            e.scalar(record.t)?;
+            e.len(record.ids.len())?;
+            for &id in &record.ids {
+                e.u64(id)?;
+            }
+        }
+        Ok(e.w.flush()?)
+    }
+
+    /// Reads a simulation from a checkpoint written by `write_checkpoint`.
+    /// Fails if the config is invalid or body ids are repeated.
+    pub fn read_checkpoint<R: Read>(r: R) -> Result<Self, NBodyError> {
+        let mut d = Decoder { r };
+        if d.bytes::<8>()? != CHECKPOINT_MAGIC {
+            return Err(NBodyError::InvalidCheckpoint("not a checkpoint".to_string()));
+        }
+        let version = d.u32()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(NBodyError::UnsupportedCheckpointVersion(version));
+        }
+        let dim = d.u32()? as usize;
+        if dim != V::DIM {
+            return Err(NBodyError::InvalidCheckpoint(format!("expected {} dimensions, got {}", V::DIM, dim)));
+        }
+
+        let config = read_config(&mut d)?;
+        let mut sim = Self::empty(0, config);
+        sim.t = d.scalar()?;
+        sim.step = d.u64()?;
+        sim.rng = SimRng::from_state(d.u64()?);
+        let next_id = d.u64()?;
+
+        // The count comes from the file, so it only limits the initial capacity
+        let n = d.len()?;
+        let mut ids: Vec<u64> = Vec::with_capacity(n.min(1 << 16));
+        for _ in 0..n {
+            ids.push(d.u64()?);
+            let kind = read_kind(&mut d)?;
+            let motion = read_motion(&mut d)?;
+            let m = d.scalar()?;
+            let radius = d.scalar()?;
+            let i = sim.add_body(&MovingBody { m, r: d.vector()?, v: d.vector()? }, kind);
+            sim.a[i] = d.vector()?;
+            sim.j[i] = d.vector()?;
+            sim.radius[i] = radius;
+            sim.motion[i] = motion;
+            for _ in 0..d.len()? {
+                sim.accretion_history[i].push(AccretionRecord {
+                    t: d.scalar()?,
+                    absorbed: d.u64()?,
+                    m_accreted: d.scalar()?,
+                    m_total: d.scalar()?,
+                });
+            }
+        }
+        sim.restore_ids(ids, next_id)?;
+
+        for _ in 0..d.len()? {
+            sim.mergers.push(read_merger(&mut d)?);
+        }
+        for _ in 0..d.len()? {
+            let t = d.scalar()?;
+            let ids = (0..d.len()?).map(|_| d.u64()).collect::<Result<Vec<u64>, NBodyError>>()?;
+            sim.non_finite_log.push(NonFiniteRecord { t, ids });
+        }
+        Ok(sim)
+    }
+
+    /// Saves a checkpoint to a file.
+    pub fn save_checkpoint<P: AsRef<FilePath>>(&self, path: P) -> Result<(), NBodyError> {
+        self.write_checkpoint(BufWriter::new(File::create(path)?))
+    }
+
+    /// Loads a simulation from a checkpoint file.
+    pub fn load_checkpoint<P: AsRef<FilePath>>(path: P) -> Result<Self, NBodyError> {
+        Self::read_checkpoint(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfigBuilder, NBodySimulation3D, NBodyError, MovingBody3D, Motion, Path, generate_galaxy, nbody_barnes_hut};
+
+    #[test]
+    fn test_checkpoint() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(3).build().unwrap();
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(100, config);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+        sim.motion[0] = Motion::Pinned;
+        for _ in 0..20 {
+            nbody_barnes_hut(&mut sim, 0.1, 1.).unwrap();
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+        sim.write_checkpoint(&mut buf).unwrap();
+        let mut restored = NBodySimulation3D::read_checkpoint(&buf[..]).unwrap();
+
+        // Both runs continue identically, including respawns driven by the RNG
+        for _ in 0..20 {
+            nbody_barnes_hut(&mut sim, 0.1, 1.).unwrap();
+            nbody_barnes_hut(&mut restored, 0.1, 1.).unwrap();
+        }
+        assert_eq!(restored.t, sim.t);
+        assert_eq!(restored.step, sim.step);
+        assert_eq!(restored.r, sim.r);
+        assert_eq!(restored.v, sim.v);
+        assert_eq!(restored.id, sim.id);
+        assert_eq!(restored.kind, sim.kind);
+        assert_eq!(restored.rng, sim.rng);
+        assert_eq!(restored.next_id(), sim.next_id());
+        assert_eq!(restored.index_of(sim.id[5]), Some(5));
+
+        // Custom paths can't be saved and bad data is rejected
+        sim.motion[1] = Motion::Scripted(Path::Custom(std::sync::Arc::new(|_| Vector3D::zero())));
+        assert_eq!(sim.write_checkpoint(&mut Vec::new()), Err(NBodyError::UnsavableMotion { id: sim.id[1] }));
+        buf[8] = 99;
+        assert_eq!(NBodySimulation3D::read_checkpoint(&buf[..]).unwrap_err(), NBodyError::UnsupportedCheckpointVersion(99));
+    }
+
+    #[test]
+    fn test_checkpoint_invalid() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(3).build().unwrap();
+        let sim: NBodySimulation3D = NBodySimulation3D::empty(5, config);
+        let mut buf: Vec<u8> = Vec::new();
+        sim.write_checkpoint(&mut buf).unwrap();
+
+        // min_dist follows the magic, version and dimension
+        let mut bad_config: Vec<u8> = buf.clone();
+        bad_config[16..24].copy_from_slice(&0f64.to_le_bytes());
+        assert_eq!(NBodySimulation3D::read_checkpoint(&bad_config[..]).unwrap_err(), NBodyError::InvalidMinDist(0.));
+
+        // Give the second body the id of the first. Each body is its id, kind, motion,
+        // mass, radius, four vectors and an empty accretion history.
+        let counts: Vec<u8> = [5u64.to_le_bytes(), 5u64.to_le_bytes()].concat();
+        let first: usize = buf.windows(16).position(|w| w == &counts[..]).unwrap() + 16;
+        let second: usize = first + 8 + 1 + 1 + 8 + 8 + 4 * 3 * 8 + 8;
+        assert_eq!(buf[second..second + 8], 1u64.to_le_bytes());
+        buf[second..second + 8].copy_from_slice(&0u64.to_le_bytes());
+        match NBodySimulation3D::read_checkpoint(&buf[..]) {
+            Err(NBodyError::InvalidCheckpoint(_)) => (),
+            result => panic!("expected an invalid checkpoint, got {:?}", result.map(|sim| sim.n)),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_truncated() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(3).build().unwrap();
+        let sim: NBodySimulation3D = NBodySimulation3D::empty(5, config);
+        let mut buf: Vec<u8> = Vec::new();
+        sim.write_checkpoint(&mut buf).unwrap();
+
+        // The next id and the body count are both 5. Claim a huge count, then end the file.
+        let counts: Vec<u8> = [5u64.to_le_bytes(), 5u64.to_le_bytes()].concat();
+        let at: usize = buf.windows(16).position(|w| w == &counts[..]).unwrap() + 8;
+        buf.truncate(at);
+        buf.extend_from_slice(&(u64::MAX >> 1).to_le_bytes());
+        match NBodySimulation3D::read_checkpoint(&buf[..]) {
+            Err(NBodyError::Io(_)) => (),
+            result => panic!("expected an I/O error, got {:?}", result.map(|sim| sim.n)),
+        }
+    }
+}
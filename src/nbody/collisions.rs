@@ -0,0 +1,207 @@
+//! Collision detection and inelastic merging of bodies
+use super::{NBodySimulation};
+use crate::vector::{Float, Vector};
+
+/// How close encounters between bodies are resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionMode {
+    /// Bodies within `min_dist` of a black hole are replaced by a new satellite.
+    Respawn,
+    /// Bodies whose radii overlap merge into one, conserving mass and momentum.
+    Merge,
+    /// Black holes absorb all bodies within `config.accretion_radius`, conserving mass and momentum.
+    Accrete,
+}
+
+/// Record of two bodies merging, identified by their ids.
+/// `recoil` is the velocity kick applied to the merged body, if any.
+#[derive(Debug, Clone, Copy)]
+pub struct MergerEvent<V: Vector> {
+    pub t: V::F,
+    pub survivor: u64,
+    pub absorbed: u64,
+    pub r_survivor: V,
+    pub r_absorbed: V,
+    pub m: V::F,
+    pub r: V,
+    pub recoil: V,
+}
+
+/// Finds all pairs of bodies whose radii overlap.
+/// Tracers and bodies with a non-finite position or radius never collide.
+///
+/// Uses sweep and prune along the first axis: bodies are sorted by the lower edge of their extent,
+/// and each body is only compared against bodies whose extents overlap with it on that axis.
+pub fn find_collisions<V: Vector>(sim: &NBodySimulation<V>) -> Vec<(usize, usize)> {
+    let x = |i: usize| sim.r[i].to_xy().0;
+    let mut order: Vec<usize> = sim.sources().into_iter()
+        .filter(|&i| sim.r[i].l2_sqrd().is_finite() && sim.radius[i].is_finite())
+        .collect();
+    order.sort_by(|&i, &k| (x(i) - sim.radius[i]).total_cmp(&(x(k) - sim.radius[k])));
+
+    let mut pairs = Vec::new();
+    for (idx, &i) in order.iter().enumerate() {
+        let max_x: V::F = x(i) + sim.radius[i];
+        for &k in &order[idx + 1..] {
+            if x(k) - sim.radius[k] > max_x { break }
+
+            let contact: V::F = sim.radius[i] + sim.radius[k];
+            if (sim.r[k] - sim.r[i]).l2_sqrd() < contact * contact {
+                pairs.push((usize::min(i, k), usize::max(i, k)));
+            }
+        }
+    }
+    pairs
+}
+
+/// Merges all colliding bodies, conserving mass and momentum, and logs each merger in `sim.mergers`.
+///
+/// Constrained bodies always survive, so they keep their trajectory. Otherwise black holes
+/// survive a merger with a star, and between bodies of the same kind the heavier one survives.
+/// Each body takes part in at most one merger per call.
+pub fn merge_collisions<V: Vector>(sim: &mut NBodySimulation<V>) {
+    let mut merged: Vec<bool> = vec![false; sim.n];
+    let mut absorbed: Vec<usize> = Vec::new();
+
+    for (i, k) in find_collisions(sim) {
+        if merged[i] || merged[k] { continue }
+
+        let (survivor, other) = if sim.is_constrained(i) != sim.is_constrained(k) {
+            if sim.is_constrained(i) { (i, k) } else { (k, i) }
+        } else if sim.is_blackhole(i) != sim.is_blackhole(k) {
+            if sim.is_blackhole(i) { (i, k) } else { (k, i) }
+        } else if sim.m[i] >= sim.m[k] {
+            (i, k)
+        } else {
+            (k, i)
+        };
+        merge(sim, survivor, other);
+        merged[i] = true;
+        merged[k] = true;
+        absorbed.push(other);
+    }
+
+    // Remove from the back so pending indices are not moved by earlier removals
+    absorbed.sort_unstable_by(|a, b| b.cmp(a));
+    for i in absorbed {
+        sim.remove_body(i);
+    }
+}
+
+/// Merges body `k` into body `i` and logs the merger.
+/// Body `k` is left in place and must be removed by the caller.
+fn merge<V: Vector>(sim: &mut NBodySimulation<V>, i: usize, k: usize) {
+    let (r_survivor, r_absorbed) = (sim.r[i], sim.r[k]);
+    combine(sim, i, k);
+    sim.log_merger(MergerEvent {
+        t: sim.t,
+        survivor: sim.id[i],
+        absorbed: sim.id[k],
+        r_survivor,
+        r_absorbed,
+        m: sim.m[i],
+        r: sim.r[i],
+        recoil: V::zero(),
+    });
+}
+
+/// Combines body `k` into body `i`, conserving mass and momentum.
+/// A constrained body `i` keeps its position and velocity, so only mass is conserved.
+/// Body `k` is left unchanged.
+pub fn combine<V: Vector>(sim: &mut NBodySimulation<V>, i: usize, k: usize) {
+    let m: V::F = sim.m[i] + sim.m[k];
+    if m > V::F::ZERO && !sim.is_constrained(i) {
+        sim.r[i] = (sim.r[i] * sim.m[i] + sim.r[k] * sim.m[k]) * (V::F::ONE / m);
+        sim.v[i] = (sim.v[i] * sim.m[i] + sim.v[k] * sim.m[k]) * (V::F::ONE / m);
+    }
+
+    // The combined body keeps the combined volume
+    let (ri, rk) = (sim.radius[i], sim.radius[k]);
+    sim.radius[i] = (ri * ri * ri + rk * rk * rk).cbrt();
+    sim.m[i] = m;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, Motion};
+    use super::{CollisionMode, find_collisions};
+
+    #[test]
+    fn test_merge() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.collision_mode = CollisionMode::Merge;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(3, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::from_xy(1., 0.), m: 2. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(103., 100.), v: Vector3D::from_xy(-1., 0.), m: 1. });
+        sim.set(2, &MovingBody3D { r: Vector3D::from_xy(300., 100.), v: Vector3D::zero(), m: 1. });
+        sim.radius[0] = 2.;
+        sim.radius[1] = 2.;
+        sim.radius[2] = 2.;
+        assert_eq!(find_collisions(&sim), vec![(0, 1)]);
+
+        // A body that blew up is ignored rather than breaking the sort
+        let r_2: Vector3D = sim.r[2];
+        sim.r[2].x = f32::NAN;
+        assert_eq!(find_collisions(&sim), vec![(0, 1)]);
+        sim.r[2] = r_2;
+
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert_eq!(sim.m[0], 3.);
+        assert_eq!(sim.r[0], Vector3D::from_xy(101., 100.));
+        assert_eq!(sim.v[0] * sim.m[0], Vector3D::from_xy(1., 0.));
+        assert_eq!(sim.r[1], Vector3D::from_xy(300., 100.));
+
+        assert_eq!(sim.mergers.len(), 1);
+        assert_eq!(sim.mergers[0].survivor, 0);
+        assert_eq!(sim.mergers[0].absorbed, 1);
+        assert_eq!(sim.index_of(1), None);
+    }
+
+    #[test]
+    fn test_merge_into_blackhole() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.collision_mode = CollisionMode::Merge;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(3, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::zero(), m: 1. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(200., 200.), v: Vector3D::zero(), m: 5. });
+        sim.set(2, &MovingBody3D { r: Vector3D::from_xy(105., 100.), v: Vector3D::zero(), m: 10. });
+        sim.radius[0] = 10.;
+        sim.kind[0] = BodyKind::BlackHole;
+
+        // The black hole survives even though the star is heavier
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.n, 2);
+        assert_eq!(sim.blackholes(), vec![0]);
+        assert_eq!(sim.m[0], 11.);
+        assert_eq!(sim.m[1], 5.);
+    }
+
+    #[test]
+    fn test_merge_pinned() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.collision_mode = CollisionMode::Merge;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::from_xy(5., 0.), m: 10. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(103., 100.), v: Vector3D::zero(), m: 1. });
+        sim.radius[0] = 2.;
+        sim.radius[1] = 2.;
+        sim.kind[0] = BodyKind::BlackHole;
+        sim.motion[1] = Motion::Pinned;
+
+        // The pinned body survives even against a heavier black hole, and stays in place
+        sim.integrate(0.).unwrap();
+        assert_eq!(sim.n, 1);
+        assert_eq!(sim.mergers[0].survivor, 1);
+        assert_eq!(sim.m[0], 11.);
+        assert_eq!(sim.r[0], Vector3D::from_xy(103., 100.));
+        assert_eq!(sim.v[0], Vector3D::zero());
+    }
+}
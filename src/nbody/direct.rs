@@ -1,35 +1,130 @@
 //! Direct algorithm using all-pairs force accumulation
 use super::{NBodySimulation};
-use crate::vector::{Scalar, Vector};
-use std::f32;
+use super::bodies::{BodyKind};
+use super::error::{NBodyError};
+use super::solver::{DirectSolver, SymmetricDirectSolver, nbody_step};
+use crate::vector::{Float, Vector};
 
 
 /// Runs a single timestep of the simulation using the all-pairs calculation.
- #[allow(dead_code)]
-pub fn nbody_direct<V: Vector>(sim: &mut NBodySimulation<V>, dt: Scalar) {
-    for i in 0..sim.n {
-        sim.a[i] = V::zero();
-
-        for j in 0..sim.n {
-            let d = sim.r[j] - sim.r[i];
-            let d_sqrd: Scalar = d.l2_sqrd();
-            if d_sqrd < sim.config.min_dist_sqrd {
-                continue;
+pub fn nbody_direct<V: Vector>(sim: &mut NBodySimulation<V>, dt: V::F) -> Result<(), NBodyError> {
+    nbody_step(sim, &DirectSolver, dt)
+}
+
+/// Runs a single timestep of the simulation using the symmetric all-pairs calculation.
+pub fn nbody_direct_symmetric<V: Vector>(sim: &mut NBodySimulation<V>, dt: V::F) -> Result<(), NBodyError> {
+    nbody_step(sim, &SymmetricDirectSolver, dt)
+}
+
+/// Computes the acceleration of every body using the all-pairs calculation.
+/// Results are stored in `sim.a`.
+pub fn compute_acc_direct<V: Vector>(sim: &mut NBodySimulation<V>) {
+    sim.with_sources(|sim, sources| {
+        for i in 0..sim.n {
+            sim.a[i] = V::zero();
+
+            for (&r, &m) in sources.r.iter().zip(&sources.m) {
+                let d = r - sim.r[i];
+                let d_sqrd: V::F = d.l2_sqrd();
+                if d_sqrd < sim.config.min_dist_sqrd {
+                    continue;
+                }
+
+                let inv_d_sqrd: V::F = V::F::ONE / d_sqrd;
+                let inv_d_cubed: V::F = inv_d_sqrd * inv_d_sqrd * inv_d_sqrd;
+                sim.a[i] += d * m * inv_d_cubed;
             }
+        }
+    });
+}
 
-            let inv_d_cubed: f32 = 1. / d_sqrd.powf(3.);
-            sim.a[i] += d * sim.m[j] * inv_d_cubed;
+/// Computes the acceleration of every body visiting each pair of sources once and applying
+/// equal and opposite forces, so the total momentum change is zero up to rounding.
+/// Tracers have no gravitational mass, so they only sum the forces from the sources.
+/// Results are stored in `sim.a`. Returns the number of pairs evaluated.
+pub fn compute_acc_symmetric<V: Vector>(sim: &mut NBodySimulation<V>) -> usize {
+    sim.with_sources(|sim, sources| {
+        for a in sim.a.iter_mut() {
+            *a = V::zero();
+        }
+        let mut pairs: usize = 0;
+
+        for k in 0..sources.len() {
+            for l in (k + 1)..sources.len() {
+                pairs += 1;
+                let d = sources.r[l] - sources.r[k];
+                let d_sqrd: V::F = d.l2_sqrd();
+                if d_sqrd < sim.config.min_dist_sqrd {
+                    continue;
+                }
+
+                let inv_d_sqrd: V::F = V::F::ONE / d_sqrd;
+                let f = d * (inv_d_sqrd * inv_d_sqrd * inv_d_sqrd);
+                sim.a[sources.index[k]] += f * sources.m[l];
+                sim.a[sources.index[l]] -= f * sources.m[k];
+            }
         }
-    }
 
-    sim.integrate(dt);
+        // Tracers feel the sources but pull on nothing
+        for i in 0..sim.n {
+            if sim.kind[i] != BodyKind::Tracer { continue }
+            for (&r, &m) in sources.r.iter().zip(&sources.m) {
+                pairs += 1;
+                let d = r - sim.r[i];
+                let d_sqrd: V::F = d.l2_sqrd();
+                if d_sqrd < sim.config.min_dist_sqrd {
+                    continue;
+                }
+
+                let inv_d_sqrd: V::F = V::F::ONE / d_sqrd;
+                let inv_d_cubed: V::F = inv_d_sqrd * inv_d_sqrd * inv_d_sqrd;
+                sim.a[i] += d * m * inv_d_cubed;
+            }
+        }
+        pairs
+    })
+}
+
+/// Computes the acceleration and jerk (time derivative of acceleration) of every body
+/// using the all-pairs calculation. Results are stored in `sim.a` and `sim.j`.
+pub fn compute_acc_jerk_direct<V: Vector>(sim: &mut NBodySimulation<V>) {
+    sim.with_sources(|sim, sources| {
+        for i in 0..sim.n {
+            let (a, j) = acc_jerk_direct(&sim.r, &sim.v, &sim.m, &sources.index, sim.config.min_dist_sqrd, i);
+            sim.a[i] = a;
+            sim.j[i] = j;
+        }
+    });
+}
+
+/// Computes the acceleration and jerk on body `i` from the bodies with indices in `sources`,
+/// given positions `r`, velocities `v` and masses `m`.
+pub fn acc_jerk_direct<V: Vector>(r: &[V], v: &[V], m: &[V::F], sources: &[usize], min_dist_sqrd: V::F, i: usize) -> (V, V) {
+    let mut acc = V::zero();
+    let mut jerk = V::zero();
+
+    for &j in sources {
+        let d = r[j] - r[i];
+        let d_sqrd: V::F = d.l2_sqrd();
+        if d_sqrd < min_dist_sqrd {
+            continue;
+        }
+        let dv = v[j] - v[i];
+
+        // Differentiate a = m * d / |d|^6 with respect to time.
+        let inv_d_cubed: V::F = V::F::ONE / d_sqrd.powf(V::F::from_f64(3.));
+        let rv: V::F = V::F::from_f64(6.) * d.dot(dv) / d_sqrd;
+        acc += d * m[j] * inv_d_cubed;
+        jerk += (dv - d * rv) * m[j] * inv_d_cubed;
+    }
+    (acc, jerk)
 }
 
 #[cfg(test)]
 mod test {
     use crate::vector::{Scalar, Vector, Vector3D};
-    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, generate_galaxy};
-    use super::{nbody_direct};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, generate_galaxy};
+    use super::{nbody_direct, compute_acc_direct, compute_acc_symmetric, compute_acc_jerk_direct};
 
     #[test]
     fn test_direct() {
@@ -47,6 +142,86 @@ mod test {
             m: 5e6,
         };
         generate_galaxy(&mut sim, &c);
-        nbody_direct(&mut sim, 0.1);
+        nbody_direct(&mut sim, 0.1).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_jerk() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(290., 270.), v: Vector3D::from_xy(-1., 2.), m: 1. });
+
+        // Compare the analytic jerk against a central difference of the acceleration.
+        let h: Scalar = 1e-2;
+        compute_acc_jerk_direct(&mut sim);
+        let jerk = sim.j[1];
+        let r = sim.r[1];
+        sim.r[1] = r + sim.v[1] * h;
+        compute_acc_jerk_direct(&mut sim);
+        let a_plus = sim.a[1];
+        sim.r[1] = r - sim.v[1] * h;
+        compute_acc_jerk_direct(&mut sim);
+        let a_minus = sim.a[1];
+
+        let fd = (a_plus - a_minus) * (1. / (2. * h));
+        assert!((fd - jerk).l2_sqrd().sqrt() < 1e-2 * jerk.l2_sqrd().sqrt());
+    }
+
+    #[test]
+    fn test_tracers() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(0, config);
+        sim.add_blackhole(&MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.add_body(&MovingBody3D { r: Vector3D::from_xy(300., 250.), v: Vector3D::zero(), m: 1. }, BodyKind::Star);
+        sim.add_body(&MovingBody3D { r: Vector3D::from_xy(250., 300.), v: Vector3D::zero(), m: 1e6 }, BodyKind::Tracer);
+
+        // The tracer feels the black hole but nothing feels the tracer
+        compute_acc_jerk_direct(&mut sim);
+        assert_eq!(sim.a[1].y, 0.);
+        assert!(sim.a[2].y < 0.);
+        assert_eq!(sim.a[0].y, 0.);
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(50, config);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+        compute_acc_symmetric(&mut sim);
+        let without_tracer: Vec<Vector3D> = sim.a.clone();
+
+        // The tracer is pulled in, but its mass is ignored on the other side of every pair
+        let tracer = sim.add_body(&MovingBody3D { r: Vector3D::from_xy(250., 300.), v: Vector3D::zero(), m: 1e6 }, BodyKind::Tracer);
+        compute_acc_symmetric(&mut sim);
+        assert_eq!(&sim.a[..tracer], &without_tracer[..]);
+        assert!(sim.a[tracer].y < 0.);
+
+        // The forces between non-tracers cancel, so the total momentum change is zero
+        let mut dp: Vector3D = Vector3D::zero();
+        let mut scale: Scalar = 0.;
+        for i in sim.sources() {
+            dp += sim.a[i] * sim.m[i];
+            scale += (sim.a[i] * sim.m[i]).norm();
+        }
+        assert!(dp.norm() < 1e-5 * scale);
+
+        // Tracers add one pair per source, not one per body, and match the all-pairs result
+        for k in 0..2000 {
+            let r = Vector3D::from_xy(100. + (k % 50) as Scalar * 6., 100. + (k / 50) as Scalar * 6.);
+            sim.add_body(&MovingBody3D { r, v: Vector3D::zero(), m: 0. }, BodyKind::Tracer);
+        }
+        let pairs: usize = compute_acc_symmetric(&mut sim);
+        assert_eq!(pairs, 50 * 49 / 2 + 2001 * 50);
+        let symmetric: Vec<Vector3D> = sim.a.clone();
+        compute_acc_direct(&mut sim);
+        assert_eq!(&symmetric[tracer..], &sim.a[tracer..]);
+    }
+}
@@ -0,0 +1,94 @@
+//! Errors reported by the simulation
+use std::error::Error;
+use std::fmt;
+
+/// Error type for configuring and running a simulation.
+/// Values are converted to `f64` so the error does not depend on the simulation's precision.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NBodyError {
+    /// `min_dist` must be finite and positive.
+    InvalidMinDist(f64),
+    /// `min_r` must not be greater than `max_r` along any axis.
+    InvertedBounds { axis: usize, min: f64, max: f64 },
+    /// The simulation must have room for all of its black holes.
+    TooManyBlackHoles { n: usize, num_blackholes: usize },
+    /// `accretion_radius` must be finite and non-negative.
+    InvalidAccretionRadius(f64),
+    /// The black hole merger radius must be finite and non-negative.
+    InvalidMergerRadius(f64),
+    /// The black hole recoil speed must be finite and non-negative.
+    InvalidRecoilSpeed(f64),
+    /// The block timestep level must be at most `MAX_BLOCK_LEVEL`.
+    InvalidMaxLevel(usize),
+    /// The largest timestep `dt_max` must be finite and positive.
+    InvalidMaxTimestep(f64),
+    /// The timestep accuracy parameter `eta` must be finite and positive.
+    InvalidEta(f64),
+    /// The end time of a run must be finite.
+    InvalidEndTime(f64),
+    /// A step of timestep `dt` did not advance the simulation time past `t`.
+    StalledStep { t: f64, dt: f64 },
+    /// The number of mesh cells along each axis must be a power of two.
+    InvalidMeshSize(usize),
+    /// A mesh needs `max_r` above `min_r` along the x and y axes.
+    InvalidMeshBounds { axis: usize, min: f64, max: f64 },
+    /// The TreePM cutoff `cut * split`, in mesh cells, must be positive and below half the mesh.
+    InvalidTreePmCutoff(f64),
+    /// Bodies with the given ids reached a non-finite mass, position or velocity at time `t`.
+    NonFinite { t: f64, ids: Vec<u64> },
+    /// Reading or writing a checkpoint failed.
+    Io(String),
+    /// The data is not a valid checkpoint.
+    InvalidCheckpoint(String),
+    /// The checkpoint was written by an unsupported version of the format.
+    UnsupportedCheckpointVersion(u32),
+    /// The body follows a custom path, which can't be saved in a checkpoint.
+    UnsavableMotion { id: u64 },
+}
+
+impl fmt::Display for NBodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NBodyError::InvalidMinDist(d) =>
+                write!(f, "min_dist must be finite and positive, got {}", d),
+            NBodyError::InvertedBounds { axis, min, max } =>
+                write!(f, "min_r ({}) is greater than max_r ({}) along axis {}", min, max, axis),
+            NBodyError::TooManyBlackHoles { n, num_blackholes } =>
+                write!(f, "{} black holes do not fit in a simulation of {} bodies", num_blackholes, n),
+            NBodyError::InvalidAccretionRadius(r) =>
+                write!(f, "accretion_radius must be finite and non-negative, got {}", r),
+            NBodyError::InvalidMergerRadius(r) =>
+                write!(f, "black hole merger radius must be finite and non-negative, got {}", r),
+            NBodyError::InvalidRecoilSpeed(s) =>
+                write!(f, "black hole recoil speed must be finite and non-negative, got {}", s),
+            NBodyError::InvalidMaxLevel(level) =>
+                write!(f, "block timestep level must be at most {}, got {}", super::block::MAX_BLOCK_LEVEL, level),
+            NBodyError::InvalidMaxTimestep(dt) =>
+                write!(f, "dt_max must be finite and positive, got {}", dt),
+            NBodyError::InvalidEta(eta) =>
+                write!(f, "eta must be finite and positive, got {}", eta),
+            NBodyError::InvalidEndTime(t) =>
+                write!(f, "end time must be finite, got {}", t),
+            NBodyError::StalledStep { t, dt } =>
+                write!(f, "step with dt = {} did not advance the time past t = {}", dt, t),
+            NBodyError::InvalidMeshSize(size) =>
+                write!(f, "mesh size must be a power of two, got {}", size),
+            NBodyError::InvalidMeshBounds { axis, min, max } =>
+                write!(f, "mesh needs max_r ({}) above min_r ({}) along axis {}", max, min, axis),
+            NBodyError::InvalidTreePmCutoff(cutoff) =>
+                write!(f, "TreePM cutoff must be positive and below half the mesh, got {} cells", cutoff),
+            NBodyError::NonFinite { t, ids } =>
+                write!(f, "bodies {:?} reached a non-finite state at t = {}", ids, t),
+            NBodyError::Io(e) =>
+                write!(f, "checkpoint I/O failed: {}", e),
+            NBodyError::InvalidCheckpoint(reason) =>
+                write!(f, "invalid checkpoint: {}", reason),
+            NBodyError::UnsupportedCheckpointVersion(version) =>
+                write!(f, "unsupported checkpoint version {}", version),
+            NBodyError::UnsavableMotion { id } =>
+                write!(f, "body {} follows a custom path, which can't be saved", id),
+        }
+    }
+}
+
+impl Error for NBodyError {}
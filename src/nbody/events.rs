@@ -0,0 +1,177 @@
+//! Events raised during a step and observers that react to them
+use std::fmt;
+use std::mem;
+use super::{NBodySimulation};
+use super::accretion::{AccretionRecord};
+use super::collisions::{MergerEvent};
+use crate::vector::{Vector};
+
+/// Something that happened to bodies during a step. Bodies are identified by their ids.
+#[derive(Debug, Clone)]
+pub enum Event<V: Vector> {
+    /// Body `id` was replaced by the new body `new_id`, a satellite of `center`.
+    Reset { id: u64, new_id: u64, center: u64 },
+    /// Body `id` left the simulation bounds at `r`, before the boundary policy was applied.
+    OutOfBounds { id: u64, r: V },
+    /// Two bodies merged.
+    Merger(MergerEvent<V>),
+    /// Black hole `blackhole` accreted a body.
+    Accretion { blackhole: u64, record: AccretionRecord<V::F> },
+    /// Bodies reached a non-finite state at time `t`.
+    NonFinite { t: V::F, ids: Vec<u64> },
+}
+
+/// Receives events from a simulation and runs before and after each step.
+/// All methods do nothing by default.
+pub trait Observer<V: Vector> {
+    /// Called for every event, after the simulation state reflects it.
+    fn on_event(&mut self, _sim: &NBodySimulation<V>, _event: &Event<V>) {}
+
+    /// Called before forces are computed for a step.
+    fn before_step(&mut self, _sim: &NBodySimulation<V>) {}
+
+    /// Called once a step has completed successfully.
+    fn after_step(&mut self, _sim: &NBodySimulation<V>) {}
+}
+
+/// Closures can be registered directly to receive events.
+impl<V: Vector, F: FnMut(&NBodySimulation<V>, &Event<V>)> Observer<V> for F {
+    fn on_event(&mut self, sim: &NBodySimulation<V>, event: &Event<V>) {
+        self(sim, event)
+    }
+}
+
+/// Observers registered on a simulation.
+pub struct Observers<V: Vector> {
+    observers: Vec<Box<dyn Observer<V>>>,
+}
+
+impl<V: Vector> Observers<V> {
+    pub fn new() -> Self {
+        Self { observers: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.observers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+}
+
+impl<V: Vector> Default for Observers<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Vector> fmt::Debug for Observers<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.observers.len())
+    }
+}
+
+impl<V: Vector> NBodySimulation<V> {
+    /// Registers an observer, which lives as long as the simulation.
+    pub fn add_observer<O: Observer<V> + 'static>(&mut self, observer: O) {
+        self.observers.observers.push(Box::new(observer));
+    }
+
+    /// Sends an event to every observer.
+    pub fn emit(&mut self, event: Event<V>) {
+        if self.observers.is_empty() { return }
+
+        // Observers only see the simulation, so take them out while they run
+        let mut observers = mem::take(&mut self.observers);
+        for observer in observers.observers.iter_mut() {
+            observer.on_event(self, &event);
+        }
+        self.observers = observers;
+    }
+
+    /// Runs the `before_step` hook of every observer.
+    /// Called by every integrator before computing forces.
+    pub fn begin_step(&mut self) {
+        let mut observers = mem::take(&mut self.observers);
+        for observer in observers.observers.iter_mut() {
+            observer.before_step(self);
+        }
+        self.observers = observers;
+    }
+
+    /// Counts the completed step and runs the `after_step` hook of every observer.
+    /// Called by every integrator once a step has completed.
+    pub fn end_step(&mut self) {
+        self.step += 1;
+        let mut observers = mem::take(&mut self.observers);
+        for observer in observers.observers.iter_mut() {
+            observer.after_step(self);
+        }
+        self.observers = observers;
+    }
+
+    /// Logs a merger in `self.mergers` and sends it to every observer.
+    pub fn log_merger(&mut self, event: MergerEvent<V>) {
+        self.mergers.push(event);
+        self.emit(Event::Merger(event));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, NBodySimulation, MovingBody3D, BoundaryPolicy, nbody_direct};
+    use super::{Event, Observer};
+
+    #[derive(Default)]
+    struct StepCounter {
+        before: Rc<RefCell<usize>>,
+        after: Rc<RefCell<usize>>,
+    }
+
+    impl Observer<Vector3D> for StepCounter {
+        fn before_step(&mut self, _sim: &NBodySimulation<Vector3D>) {
+            *self.before.borrow_mut() += 1;
+        }
+
+        fn after_step(&mut self, _sim: &NBodySimulation<Vector3D>) {
+            *self.after.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn test_observers() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.boundary = BoundaryPolicy::Remove;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::zero(), m: 1. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(495., 100.), v: Vector3D::from_xy(10., 0.), m: 1. });
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        sim.add_observer(move |_: &NBodySimulation3D, event: &Event<Vector3D>| log.borrow_mut().push(event.clone()));
+        let counter = StepCounter::default();
+        let (before, after) = (counter.before.clone(), counter.after.clone());
+        sim.add_observer(counter);
+
+        nbody_direct(&mut sim, 1.).unwrap();
+        nbody_direct(&mut sim, 1.).unwrap();
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Event::OutOfBounds { id, r } => {
+                assert_eq!(id, 1);
+                assert!(r.x > 500.);
+            },
+            _ => panic!("unexpected event {:?}", events[0]),
+        }
+        assert_eq!(sim.n, 1);
+        assert_eq!((*before.borrow(), *after.borrow()), (2, 2));
+    }
+}
@@ -0,0 +1,146 @@
+//! Radix-2 fast Fourier transforms used by the mesh solvers
+use std::ops::{Add, Mul, Sub};
+use crate::vector::{Float, Scalar};
+
+/// Complex number with components of the simulation precision.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex<F: Float = Scalar> {
+    pub re: F,
+    pub im: F,
+}
+
+impl<F: Float> Complex<F> {
+    pub fn new(re: F, im: F) -> Self {
+        Self { re, im }
+    }
+
+    /// Constructs `e^(i * theta)`.
+    pub fn from_angle(theta: F) -> Self {
+        Self { re: theta.cos(), im: theta.sin() }
+    }
+}
+
+impl<F: Float> Add for Complex<F> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self { re: self.re + rhs.re, im: self.im + rhs.im }
+    }
+}
+
+impl<F: Float> Sub for Complex<F> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self { re: self.re - rhs.re, im: self.im - rhs.im }
+    }
+}
+
+impl<F: Float> Mul for Complex<F> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            re: self.re * rhs.re - self.im * rhs.im,
+            im: self.re * rhs.im + self.im * rhs.re,
+        }
+    }
+}
+
+/// In-place FFT of `data`, whose length must be a power of two.
+/// The inverse transform is scaled by `1 / n`, so it undoes the forward transform.
+pub fn fft<F: Float>(data: &mut [Complex<F>], inverse: bool) {
+    let n: usize = data.len();
+    assert!(n.is_power_of_two(), "FFT length {} is not a power of two", n);
+    if n < 2 { return }
+
+    // Reorder into bit-reversed order
+    let bits: u32 = n.trailing_zeros();
+    for i in 0..n {
+        let j: usize = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j { data.swap(i, j) }
+    }
+
+    // Twiddle factors for the full length, shared by every stage
+    let sign: F = if inverse { F::ONE } else { -F::ONE };
+    let step: F = sign * F::from_f64(2.) * F::PI / F::from_f64(n as f64);
+    let twiddles: Vec<Complex<F>> = (0..n / 2)
+        .map(|k| Complex::from_angle(step * F::from_f64(k as f64)))
+        .collect();
+
+    // Butterflies over blocks of doubling length
+    let mut len: usize = 2;
+    while len <= n {
+        let stride: usize = n / len;
+        for block in data.chunks_exact_mut(len) {
+            let (lo, hi) = block.split_at_mut(len / 2);
+            for (k, (a, b)) in lo.iter_mut().zip(hi.iter_mut()).enumerate() {
+                let t: Complex<F> = twiddles[k * stride] * *b;
+                *b = *a - t;
+                *a = *a + t;
+            }
+        }
+        len *= 2;
+    }
+
+    if inverse {
+        let scale: F = F::ONE / F::from_f64(n as f64);
+        for x in data.iter_mut() {
+            x.re *= scale;
+            x.im *= scale;
+        }
+    }
+}
+
+/// In-place 2D FFT of an `n` by `n` grid stored in row-major order.
+pub fn fft2<F: Float>(data: &mut [Complex<F>], n: usize, inverse: bool) {
+    assert_eq!(data.len(), n * n);
+    for row in data.chunks_exact_mut(n) {
+        fft(row, inverse);
+    }
+
+    let mut column: Vec<Complex<F>> = vec![Complex::default(); n];
+    for x in 0..n {
+        for (y, c) in column.iter_mut().enumerate() {
+            *c = data[y * n + x];
+        }
+        fft(&mut column, inverse);
+        for (y, c) in column.iter().enumerate() {
+            data[y * n + x] = *c;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Complex, fft, fft2};
+
+    #[test]
+    fn test_fft() {
+        let n: usize = 16;
+        let input: Vec<Complex<f64>> = (0..n)
+            .map(|i| Complex::new((i as f64 * 0.7).sin() + 1., (i * i % 5) as f64))
+            .collect();
+
+        // Compare against the naive DFT
+        let mut data = input.clone();
+        fft(&mut data, false);
+        for (k, x) in data.iter().enumerate() {
+            let mut sum: Complex<f64> = Complex::default();
+            for (j, y) in input.iter().enumerate() {
+                let theta: f64 = -2. * std::f64::consts::PI * (j * k) as f64 / n as f64;
+                sum = sum + *y * Complex::from_angle(theta);
+            }
+            assert!((x.re - sum.re).abs() < 1e-9 && (x.im - sum.im).abs() < 1e-9);
+        }
+
+        // The inverse transform round-trips
+        fft(&mut data, true);
+        for (x, y) in data.iter().zip(&input) {
+            assert!((x.re - y.re).abs() < 1e-12 && (x.im - y.im).abs() < 1e-12);
+        }
+
+        let mut grid: Vec<Complex<f64>> = (0..n * n).map(|i| Complex::new(i as f64, 0.)).collect();
+        fft2(&mut grid, n, false);
+        assert!((grid[0].re - (n * n * (n * n - 1) / 2) as f64).abs() < 1e-6);
+        fft2(&mut grid, n, true);
+        assert!((grid[37].re - 37.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,125 @@
+//! Detection of and recovery from non-finite positions and velocities
+use super::{NBodySimulation};
+use super::error::{NBodyError};
+use super::events::{Event};
+use crate::vector::{Float, Scalar, Vector};
+
+/// What to do when a step leaves bodies with non-finite mass, position or velocity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonFinitePolicy {
+    /// Return `NBodyError::NonFinite` and leave the state as it is.
+    Error,
+    /// Remove the bad bodies and log them in `sim.non_finite_log`.
+    Remove,
+    /// Restore the state from before the step and return `NBodyError::NonFinite`,
+    /// so the caller can retry with a smaller timestep.
+    Rollback,
+}
+
+/// Record of bodies removed for having non-finite state.
+#[derive(Debug, Clone)]
+pub struct NonFiniteRecord<F: Float = Scalar> {
+    pub t: F,
+    pub ids: Vec<u64>,
+}
+
+/// State of the simulation at the start of a step, used to roll it back.
+#[derive(Debug, Clone)]
+pub struct StepSnapshot<V: Vector> {
+    t: V::F,
+    r: Vec<V>,
+    v: Vec<V>,
+    a: Vec<V>,
+    j: Vec<V>,
+}
+
+/// Takes a snapshot of the simulation if the policy may need to roll back the coming step.
+pub fn snapshot<V: Vector>(sim: &NBodySimulation<V>) -> Option<StepSnapshot<V>> {
+    if sim.config.non_finite != NonFinitePolicy::Rollback { return None }
+    Some(StepSnapshot {
+        t: sim.t,
+        r: sim.r.clone(),
+        v: sim.v.clone(),
+        a: sim.a.clone(),
+        j: sim.j.clone(),
+    })
+}
+
+/// Checks if a vector has only finite components.
+fn is_finite<V: Vector>(x: V) -> bool {
+    (0..V::DIM).all(|k| x.component(k).is_finite())
+}
+
+/// Finds all bodies with non-finite mass, position or velocity.
+pub fn find_non_finite<V: Vector>(sim: &NBodySimulation<V>) -> Vec<usize> {
+    (0..sim.n)
+        .filter(|&i| !(sim.m[i].is_finite() && is_finite(sim.r[i]) && is_finite(sim.v[i])))
+        .collect()
+}
+
+/// Checks the state after a step and applies the configured `NonFinitePolicy`.
+/// `snapshot` must come from `snapshot` at the start of the step.
+pub fn check_finite<V: Vector>(sim: &mut NBodySimulation<V>, snapshot: Option<StepSnapshot<V>>) -> Result<(), NBodyError> {
+    let bad: Vec<usize> = find_non_finite(sim);
+    if bad.is_empty() { return Ok(()) }
+
+    let ids: Vec<u64> = bad.iter().map(|&i| sim.id[i]).collect();
+    sim.emit(Event::NonFinite { t: sim.t, ids: ids.clone() });
+    match sim.config.non_finite {
+        NonFinitePolicy::Error => Err(NBodyError::NonFinite { t: sim.t.to_f64(), ids }),
+        NonFinitePolicy::Remove => {
+            for &i in bad.iter().rev() {
+                sim.remove_body(i);
+            }
+            sim.non_finite_log.push(NonFiniteRecord { t: sim.t, ids });
+            Ok(())
+        },
+        NonFinitePolicy::Rollback => {
+            let t = sim.t.to_f64();
+            if let Some(snapshot) = snapshot {
+                sim.t = snapshot.t;
+                sim.r = snapshot.r;
+                sim.v = snapshot.v;
+                sim.a = snapshot.a;
+                sim.j = snapshot.j;
+            }
+            Err(NBodyError::NonFinite { t, ids })
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, NBodyError};
+    use super::{NonFinitePolicy};
+
+    fn bad_sim(policy: NonFinitePolicy) -> NBodySimulation3D {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut config = NBodyConfig3D::new(10., min_r, max_r);
+        config.non_finite = policy;
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(100., 100.), v: Vector3D::zero(), m: 1. });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(200., 100.), v: Vector3D::zero(), m: 1. });
+        sim.a[1] = Vector3D::from_xy(Scalar::INFINITY, 0.);
+        sim
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let mut sim = bad_sim(NonFinitePolicy::Error);
+        assert_eq!(sim.integrate(1.), Err(NBodyError::NonFinite { t: 1., ids: vec![1] }));
+
+        let mut sim = bad_sim(NonFinitePolicy::Remove);
+        assert_eq!(sim.integrate(1.), Ok(()));
+        assert_eq!(sim.n, 1);
+        assert_eq!(sim.non_finite_log[0].ids, vec![1]);
+
+        let mut sim = bad_sim(NonFinitePolicy::Rollback);
+        assert!(sim.integrate(1.).is_err());
+        assert_eq!(sim.t, 0.);
+        assert_eq!(sim.r[1], Vector3D::from_xy(200., 100.));
+        assert_eq!(sim.v[1], Vector3D::zero());
+    }
+}
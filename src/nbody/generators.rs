@@ -1,36 +1,37 @@
+use rand::Rng;
 use rand_distr::{Uniform, Distribution, Normal};
-use std::f32::{consts::PI};
+use std::f64::{consts::PI};
 use super::simulation::{NBodySimulation};
-use super::bodies::{Scalar, Vector, MovingBody};
+use super::bodies::{Vector, MovingBody, BodyKind};
+use crate::vector::{Float};
 
 
 // Generates a satelite around the galaxy center.
-pub fn generate_satellite<V: Vector>(c: &MovingBody<V>) -> MovingBody<V> {
+pub fn generate_satellite<V: Vector, R: Rng>(c: &MovingBody<V>, rng: &mut R) -> MovingBody<V> {
     // Generate a randon polar coordinate and mass
-    let mut rng = rand::thread_rng();
-    let uniform: Uniform<Scalar> = Uniform::new(0., 2. * PI);
-    let r_norm: Normal<Scalar> = Normal::new(1., 1.).unwrap();
-    let m_norm: Normal<Scalar> = Normal::new(1., 1.).unwrap();
+    let uniform: Uniform<f64> = Uniform::new(0., 2. * PI);
+    let r_norm: Normal<f64> = Normal::new(1., 1.).unwrap();
+    let m_norm: Normal<f64> = Normal::new(1., 1.).unwrap();
 
-    let theta: Scalar = uniform.sample(&mut rng);
-    let mut r: Scalar = r_norm.sample(&mut rng);
-    let mut m: Scalar = m_norm.sample(&mut rng);
-    r = Scalar::min(30. * r.abs() + 20., 250.);
-    m = Scalar::min(m.abs() + 1e-2, 3.);
+    let theta: V::F = V::F::from_f64(uniform.sample(rng));
+    let r: f64 = r_norm.sample(rng);
+    let m: f64 = m_norm.sample(rng);
+    let r: V::F = V::F::from_f64(f64::min(30. * r.abs() + 20., 250.));
+    let m: V::F = V::F::from_f64(f64::min(m.abs() + 1e-2, 3.));
 
     // Calculate position
     let (crx, cry) = c.r.to_xy();
-    let rx: Scalar = r * theta.cos() + crx;
-    let ry: Scalar = r * theta.sin() + cry;
+    let rx: V::F = r * theta.cos() + crx;
+    let ry: V::F = r * theta.sin() + cry;
     
     // Calculate velocity, which should increase with center's mass, the 
-    let dx: Scalar = crx - rx;
-    let dy: Scalar = cry - ry;
-    let d: Scalar = (dx * dx + dy * dy).sqrt();
-    let s: Scalar = 1.00025e0 * (c.m).sqrt() / r / r;
+    let dx: V::F = crx - rx;
+    let dy: V::F = cry - ry;
+    let d: V::F = (dx * dx + dy * dy).sqrt();
+    let s: V::F = V::F::from_f64(1.00025e0) * (c.m).sqrt() / r / r;
 
-    let vx: Scalar = s * dy / d;
-    let vy: Scalar = s * -dx / d;
+    let vx: V::F = s * dy / d;
+    let vy: V::F = s * -dx / d;
 
     MovingBody {
         r: V::from_xy(rx, ry),
@@ -39,21 +40,40 @@ pub fn generate_satellite<V: Vector>(c: &MovingBody<V>) -> MovingBody<V> {
     }
 }
 
-/// Generates a simple galaxy
+/// Adds a black hole to the simulation
 pub fn generate_blackhole<V: Vector>(sim: &mut NBodySimulation<V>, c: &MovingBody<V>) {
-    // Initialize with supermassive object in middle
-    sim.set(sim.config.num_blackholes, c);
-    sim.config.num_blackholes += 1;
+    let i = sim.add_blackhole(c);
+    sim.radius[i] = sim.config.min_dist;
+}
+
+/// Adds `n` satellites of the given kind around a galaxy center
+pub fn generate_satellites<V: Vector>(sim: &mut NBodySimulation<V>, c: &MovingBody<V>, n: usize, kind: BodyKind) {
+    for _ in 0..n {
+        let body = generate_satellite(c, &mut sim.rng);
+        sim.add_body(&body, kind);
+    }
+}
+
+/// Adds `n` massless tracers around a galaxy center
+pub fn generate_tracers<V: Vector>(sim: &mut NBodySimulation<V>, c: &MovingBody<V>, n: usize) {
+    for _ in 0..n {
+        let mut tracer = generate_satellite(c, &mut sim.rng);
+        tracer.m = V::F::ZERO;
+        sim.add_body(&tracer, BodyKind::Tracer);
+    }
 }
 
 /// Generates a simple galaxy
 pub fn generate_galaxy<V: Vector>(sim: &mut NBodySimulation<V>, c: &MovingBody<V>) {
     // Initialize with supermassive object in middle
     sim.set(0, c);
-    sim.config.num_blackholes = 1;
+    sim.radius[0] = sim.config.min_dist;
+    sim.kind[0] = BodyKind::BlackHole;
 
     // Add all other objects as satellites.
     for i in 1..sim.n {
-        sim.set(i, &generate_satellite(c));
+        let body = generate_satellite(c, &mut sim.rng);
+        sim.set(i, &body);
+        sim.kind[i] = BodyKind::Star;
     }
 }
@@ -0,0 +1,84 @@
+//! Fourth-order Hermite predictor-corrector integrator
+use super::{NBodySimulation};
+use super::direct::{compute_acc_jerk_direct};
+use super::error::{NBodyError};
+use super::finite::{snapshot, check_finite};
+use crate::vector::{Float, Vector};
+
+/// Runs a single timestep of the simulation using the fourth-order Hermite scheme.
+///
+/// Accelerations and jerks are evaluated with the all-pairs calculation,
+/// once at the start of the step and once at the predicted positions.
+pub fn nbody_hermite<V: Vector>(sim: &mut NBodySimulation<V>, dt: V::F) -> Result<(), NBodyError> {
+    sim.begin_step();
+    let snapshot = snapshot(sim);
+    compute_acc_jerk_direct(sim);
+    let r0 = sim.r.clone();
+    let v0 = sim.v.clone();
+    let a0 = sim.a.clone();
+    let j0 = sim.j.clone();
+
+    let dt2: V::F = dt * dt / V::F::from_f64(2.);
+    let dt3: V::F = dt * dt * dt / V::F::from_f64(6.);
+
+    // Predict positions and velocities with a third order Taylor expansion
+    let t1: V::F = sim.t + dt;
+    for i in 0..sim.n {
+        if let Some((r, v)) = sim.constrained_state(i, t1) {
+            sim.r[i] = r;
+            sim.v[i] = v;
+            continue;
+        }
+        sim.r[i] = r0[i] + v0[i] * dt + a0[i] * dt2 + j0[i] * dt3;
+        sim.v[i] = v0[i] + a0[i] * dt + j0[i] * dt2;
+    }
+
+    // Evaluate at the predicted state
+    compute_acc_jerk_direct(sim);
+
+    // Correct using the acceleration and jerk at both ends of the step
+    let dt12: V::F = dt * dt / V::F::from_f64(12.);
+    let half_dt: V::F = dt / V::F::from_f64(2.);
+    for i in 0..sim.n {
+        // Pinned and scripted bodies are already at their final state
+        if sim.constrained_state(i, t1).is_some() { continue }
+        sim.v[i] = v0[i] + (a0[i] + sim.a[i]) * half_dt + (j0[i] - sim.j[i]) * dt12;
+        sim.r[i] = r0[i] + (v0[i] + sim.v[i]) * half_dt + (a0[i] - sim.a[i]) * dt12;
+    }
+
+    sim.t = t1;
+    check_finite(sim, snapshot)?;
+    sim.resolve_encounters();
+    sim.end_step();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind};
+    use super::{nbody_hermite};
+
+    #[test]
+    fn test_hermite_circular_orbit() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+
+        // Satellite on a circular orbit, using the same speed as `generate_satellite`.
+        let m: Scalar = 5e6;
+        let radius: Scalar = 50.;
+        let s: Scalar = m.sqrt() / radius / radius;
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(300., 250.), v: Vector3D::from_xy(0., s), m: 1e-2 });
+        sim.kind[0] = BodyKind::BlackHole;
+
+        // Circular orbits are unstable under this force law, so only follow a third of an orbit.
+        for _ in 0..200 {
+            nbody_hermite(&mut sim, 0.5).unwrap();
+        }
+        let d = (sim.r[1] - sim.r[0]).l2_sqrd().sqrt();
+        assert!((d - radius).abs() < 1e-2, "radius drifted to {}", d);
+    }
+}
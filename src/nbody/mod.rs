@@ -1,15 +1,56 @@
 //! N Body simulation
 
+pub mod accretion;
 pub mod barnes_hut;
+pub mod blackholes;
+pub mod block;
+pub mod boundary;
 pub mod bodies;
+pub mod builder;
+pub mod checkpoint;
+pub mod collisions;
 pub mod direct;
+pub mod error;
+pub mod events;
+pub mod fft;
+pub mod finite;
 pub mod generators;
+pub mod hermite;
+pub mod motion;
+pub mod pm;
+pub mod rng;
+pub mod run;
+pub mod simd;
 pub mod simulation;
+pub mod solver;
+#[cfg(test)]
+mod testing;
+pub mod timestep;
+pub mod treepm;
 
-pub use crate::vector::Vector3D;
+pub use crate::vector::{Vector2D, Vector3D};
 
-pub use self::barnes_hut::nbody_barnes_hut;
-pub use self::bodies::{Body, MovingBody, MovingBody3D};
-pub use self::direct::{nbody_direct};
-pub use self::generators::{generate_galaxy, generate_satellite, generate_blackhole};
-pub use self::simulation::{NBodyConfig, NBodyConfig3D, NBodySimulation, NBodySimulation3D};
+pub use self::accretion::{AccretionRecord, accrete};
+pub use self::barnes_hut::{nbody_barnes_hut, nbody_barnes_hut_adaptive, compute_acc_barnes_hut, build_source_tree};
+pub use self::blackholes::{BlackHoleMergerConfig, merge_blackholes};
+pub use self::block::{nbody_block_hermite, BlockTimestepConfig, BlockStepStats, MAX_BLOCK_LEVEL};
+pub use self::boundary::{BoundaryPolicy, apply_boundary};
+pub use self::bodies::{Body, BodyKind, MovingBody, MovingBody2D, MovingBody3D};
+pub use self::builder::{NBodyConfigBuilder, NBodySimulationBuilder};
+pub use self::checkpoint::{CHECKPOINT_MAGIC, CHECKPOINT_VERSION};
+pub use self::collisions::{CollisionMode, MergerEvent, find_collisions, merge_collisions, combine};
+pub use self::direct::{nbody_direct, nbody_direct_symmetric, acc_jerk_direct, compute_acc_direct, compute_acc_symmetric, compute_acc_jerk_direct};
+pub use self::error::NBodyError;
+pub use self::events::{Event, Observer, Observers};
+pub use self::finite::{NonFinitePolicy, NonFiniteRecord, check_finite, find_non_finite};
+pub use self::generators::{generate_galaxy, generate_satellite, generate_satellites, generate_tracers, generate_blackhole};
+pub use self::hermite::nbody_hermite;
+pub use self::motion::{Motion, Path};
+pub use self::pm::{MeshBoundary, PmSolver, compute_acc_pm, compute_acc_mesh};
+pub use self::rng::SimRng;
+pub use self::run::{StepMethod, StepSummary};
+pub use self::simd::{SoaBodies, SimdDirectSolver, SimdBarnesHutSolver, acc_soa, has_avx2, compute_acc_direct_simd, compute_acc_barnes_hut_simd};
+pub use self::simulation::{NBodyConfig, NBodyConfig2D, NBodyConfig3D, NBodySimulation, NBodySimulation2D, NBodySimulation3D, SourceBuffer};
+pub use self::solver::{Solver, DirectSolver, SymmetricDirectSolver, BarnesHutSolver, nbody_step};
+pub use self::timestep::{AdaptiveTimestep, TimestepCriterion};
+pub use self::treepm::{TreePmSolver, compute_acc_treepm, erfc};
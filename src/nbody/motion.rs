@@ -0,0 +1,104 @@
+//! Motion modes for bodies that are not moved by gravity
+use std::fmt;
+use std::sync::Arc;
+use crate::vector::{Float, Vector};
+
+/// Step used to differentiate custom paths.
+const PATH_DT: f64 = 1e-3;
+
+/// Prescribed trajectory of a scripted body as a function of simulation time.
+#[derive(Clone)]
+pub enum Path<V: Vector> {
+    /// Circular orbit in the x-y plane around `center`.
+    Circular { center: V, radius: V::F, omega: V::F, phase: V::F },
+    /// Arbitrary position as a function of time.
+    Custom(Arc<dyn Fn(V::F) -> V + Send + Sync>),
+}
+
+impl<V: Vector> Path<V> {
+    /// Gets the position on the path at time `t`.
+    pub fn position(&self, t: V::F) -> V {
+        match self {
+            Path::Circular { center, radius, omega, phase } => {
+                let theta: V::F = *omega * t + *phase;
+                *center + V::from_xy(theta.cos(), theta.sin()) * *radius
+            },
+            Path::Custom(f) => f(t),
+        }
+    }
+
+    /// Gets the velocity along the path at time `t`.
+    /// Custom paths are differentiated numerically.
+    pub fn velocity(&self, t: V::F) -> V {
+        match self {
+            Path::Circular { radius, omega, phase, .. } => {
+                let theta: V::F = *omega * t + *phase;
+                V::from_xy(-theta.sin(), theta.cos()) * (*radius * *omega)
+            },
+            Path::Custom(f) => {
+                let h: V::F = V::F::from_f64(PATH_DT);
+                (f(t + h) - f(t - h)) * (V::F::ONE / (h + h))
+            },
+        }
+    }
+}
+
+impl<V: Vector> fmt::Debug for Path<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Path::Circular { center, radius, omega, phase } => f
+                .debug_struct("Circular")
+                .field("center", center)
+                .field("radius", radius)
+                .field("omega", omega)
+                .field("phase", phase)
+                .finish(),
+            Path::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// How a body is moved by the integrators.
+#[derive(Debug, Clone)]
+pub enum Motion<V: Vector> {
+    /// Moved by gravity.
+    Free,
+    /// Acts as a source of gravity but never moves.
+    Pinned,
+    /// Follows a prescribed path regardless of the forces on it.
+    Scripted(Path<V>),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, nbody_direct, nbody_hermite};
+    use super::{Motion, Path};
+
+    #[test]
+    fn test_motion() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(0, config);
+        let center = Vector3D::from_xy(250., 250.);
+        sim.add_blackhole(&MovingBody3D { r: center, v: Vector3D::zero(), m: 5e6 });
+        sim.add_blackhole(&MovingBody3D { r: Vector3D::from_xy(400., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.motion[0] = Motion::Pinned;
+        sim.motion[1] = Motion::Scripted(Path::Circular { center, radius: 150., omega: 0.01, phase: 0. });
+
+        for _ in 0..10 {
+            nbody_direct(&mut sim, 1.).unwrap();
+        }
+        for _ in 0..10 {
+            nbody_hermite(&mut sim, 1.).unwrap();
+        }
+
+        // The pinned black hole stays put and the scripted one stays on its orbit
+        assert_eq!(sim.r[0], center);
+        assert_eq!(sim.v[0], Vector3D::zero());
+        let theta: Scalar = 0.2;
+        let expected = Vector3D::from_xy(150. * theta.cos(), 150. * theta.sin());
+        assert!((sim.r[1] - center - expected).l2_sqrd() < 1e-6);
+    }
+}
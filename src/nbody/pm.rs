@@ -0,0 +1,348 @@
+//! Particle-mesh force calculation on a 2D grid
+use std::sync::Mutex;
+use super::{NBodyConfig, NBodySimulation};
+use super::error::{NBodyError};
+use super::fft::{Complex, fft2};
+use super::solver::{Solver};
+use crate::vector::{Float, Scalar, Vector};
+
+/// How the mesh treats the edges of the simulation box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshBoundary {
+    /// Bodies only interact inside the box, using a zero-padded grid.
+    Isolated,
+    /// The box repeats, so bodies interact across opposite walls.
+    Periodic,
+}
+
+/// Checks that a mesh has a power of two cells along each axis, as the FFT requires.
+pub(crate) fn check_mesh_size(size: usize) -> Result<(), NBodyError> {
+    if !size.is_power_of_two() {
+        return Err(NBodyError::InvalidMeshSize(size));
+    }
+    Ok(())
+}
+
+/// Checks that the bounds of a mesh have a positive width along x and y, so its cells do too.
+pub(crate) fn check_mesh_bounds<V: Vector>(config: &NBodyConfig<V>) -> Result<(), NBodyError> {
+    for axis in 0..2 {
+        let (min, max) = (config.min_r.component(axis), config.max_r.component(axis));
+        let width: V::F = max - min;
+        if !width.is_finite() || width <= V::F::ZERO {
+            return Err(NBodyError::InvalidMeshBounds { axis, min: min.to_f64(), max: max.to_f64() });
+        }
+    }
+    Ok(())
+}
+
+/// Cloud-in-cell weights of a point: the four cells it overlaps and their weights.
+fn cic<F: Float>(x: F, y: F, origin: (F, F), h: (F, F), size: usize) -> [(usize, F); 4] {
+    // Cell centers are at origin + (i + 0.5) * h
+    let u: F = (x - origin.0) / h.0 - F::from_f64(0.5);
+    let v: F = (y - origin.1) / h.1 - F::from_f64(0.5);
+    let (u0, v0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - u0, v - v0);
+
+    // Cells outside the grid wrap around, which is exact for periodic meshes.
+    // For isolated meshes the point is inside the box, so its cells are at most one past the box
+    // and land in the zero padding.
+    let wrap = |i: F| (i.to_f64() as i64).rem_euclid(size as i64) as usize;
+    let (ix0, iy0) = (wrap(u0), wrap(v0));
+    let (ix1, iy1) = ((ix0 + 1) % size, (iy0 + 1) % size);
+    [
+        (iy0 * size + ix0, (F::ONE - fx) * (F::ONE - fy)),
+        (iy0 * size + ix1, fx * (F::ONE - fy)),
+        (iy1 * size + ix0, (F::ONE - fx) * fy),
+        (iy1 * size + ix1, fx * fy),
+    ]
+}
+
+/// Pairwise kernel of a mesh in Fourier space, kept with the mesh it was built for
+/// so it can be reused while the mesh stays the same.
+#[derive(Debug, Clone)]
+pub(crate) struct MeshKernel<F: Float> {
+    size: usize,
+    boundary: MeshBoundary,
+    /// Lower and upper corners of the box over x and y.
+    min: (F, F),
+    max: (F, F),
+    gx: Vec<Complex<F>>,
+    gy: Vec<Complex<F>>,
+}
+
+impl<F: Float> MeshKernel<F> {
+    /// Transforms the acceleration `d * kernel(|d|^2)` from a unit mass at every offset `d` of a mesh
+    /// with `size` cells along each axis over the x and y bounds of `config`.
+    pub(crate) fn new<V, K>(config: &NBodyConfig<V>, size: usize, boundary: MeshBoundary, kernel: K) -> Result<Self, NBodyError>
+        where V: Vector<F = F>, K: Fn(F) -> F {
+        check_mesh_size(size)?;
+        check_mesh_bounds(config)?;
+        let min: (F, F) = config.min_r.to_xy();
+        let max: (F, F) = config.max_r.to_xy();
+        let cells: F = F::from_f64(size as f64);
+        let h: (F, F) = ((max.0 - min.0) / cells, (max.1 - min.1) / cells);
+        let n: usize = mesh_len(size, boundary);
+
+        // Acceleration at offset k from a unit mass, using the nearest image of each offset
+        let mut gx: Vec<Complex<F>> = vec![Complex::default(); n * n];
+        let mut gy: Vec<Complex<F>> = vec![Complex::default(); n * n];
+        let offset = |k: usize| F::from_f64(if k < n / 2 { k as f64 } else { k as f64 - n as f64 });
+        for ky in 0..n {
+            for kx in 0..n {
+                // A body exerts no force on itself, so the kernel isn't evaluated at zero offset
+                if kx == 0 && ky == 0 { continue }
+                let dx: F = offset(kx) * h.0;
+                let dy: F = offset(ky) * h.1;
+                let s: F = kernel(dx * dx + dy * dy);
+                gx[ky * n + kx].re = -dx * s;
+                gy[ky * n + kx].re = -dy * s;
+            }
+        }
+        fft2(&mut gx, n, false);
+        fft2(&mut gy, n, false);
+        Ok(Self { size, boundary, min, max, gx, gy })
+    }
+
+    /// Checks whether the kernel was built for this mesh.
+    pub(crate) fn fits<V: Vector<F = F>>(&self, config: &NBodyConfig<V>, size: usize, boundary: MeshBoundary) -> bool {
+        self.size == size && self.boundary == boundary
+            && self.min == config.min_r.to_xy() && self.max == config.max_r.to_xy()
+    }
+
+    /// Computes the x and y acceleration of every body by convolving the mesh density with the kernel.
+    /// The simulation must have the bounds the kernel was built for.
+    /// On an isolated mesh, bodies outside the box neither exert nor receive mesh forces.
+    /// Results are stored in `sim.a`.
+    pub(crate) fn apply<V: Vector<F = F>>(&self, sim: &mut NBodySimulation<V>) {
+        let (min_x, min_y) = self.min;
+        let (max_x, max_y) = self.max;
+        let on_mesh = |x: F, y: F| match self.boundary {
+            MeshBoundary::Isolated => x >= min_x && x <= max_x && y >= min_y && y <= max_y,
+            MeshBoundary::Periodic => true,
+        };
+        let cells: F = F::from_f64(self.size as f64);
+        let h: (F, F) = ((max_x - min_x) / cells, (max_y - min_y) / cells);
+        let n: usize = mesh_len(self.size, self.boundary);
+
+        // Assign the mass of every source to the mesh
+        let mut rho: Vec<Complex<F>> = vec![Complex::default(); n * n];
+        sim.with_sources(|_, sources| {
+            for (&r, &m) in sources.r.iter().zip(&sources.m) {
+                let (x, y) = r.to_xy();
+                if !on_mesh(x, y) { continue }
+                for (cell, w) in cic(x, y, (min_x, min_y), h, n).iter() {
+                    rho[*cell].re += m * *w;
+                }
+            }
+        });
+
+        // Convolve in Fourier space
+        fft2(&mut rho, n, false);
+        let mut gx: Vec<Complex<F>> = self.gx.iter().zip(&rho).map(|(g, rho)| *g * *rho).collect();
+        let mut gy: Vec<Complex<F>> = self.gy.iter().zip(&rho).map(|(g, rho)| *g * *rho).collect();
+        fft2(&mut gx, n, true);
+        fft2(&mut gy, n, true);
+
+        // Interpolate the mesh accelerations back to the bodies with the same weights
+        for i in 0..sim.n {
+            let (x, y) = sim.r[i].to_xy();
+            let (mut ax, mut ay) = (F::ZERO, F::ZERO);
+            if on_mesh(x, y) {
+                for (cell, w) in cic(x, y, (min_x, min_y), h, n).iter() {
+                    ax += gx[*cell].re * *w;
+                    ay += gy[*cell].re * *w;
+                }
+            }
+            sim.a[i] = V::from_xy(ax, ay);
+        }
+    }
+}
+
+/// Number of cells along each axis of the grid, which is doubled for isolated meshes
+/// so the convolution doesn't wrap around.
+fn mesh_len(size: usize, boundary: MeshBoundary) -> usize {
+    match boundary {
+        MeshBoundary::Isolated => 2 * size,
+        MeshBoundary::Periodic => size,
+    }
+}
+
+/// Computes the x and y acceleration of every body by convolving the mesh density
+/// with a pairwise kernel, where a source at offset `d` contributes `d * m * kernel(|d|^2)`.
+/// `size` is the number of cells along each axis of the box and must be a power of two,
+/// and the box must have a positive width along x and y.
+/// On an isolated mesh, bodies outside the box neither exert nor receive mesh forces.
+/// Results are stored in `sim.a`.
+pub fn compute_acc_mesh<V, K>(sim: &mut NBodySimulation<V>, size: usize, boundary: MeshBoundary, kernel: K) -> Result<(), NBodyError>
+    where V: Vector, K: Fn(V::F) -> V::F {
+    MeshKernel::new(&sim.config, size, boundary, kernel)?.apply(sim);
+    Ok(())
+}
+
+/// Pairwise kernel of the force, which is zero below `min_dist`.
+fn pm_kernel<F: Float>(min_dist_sqrd: F) -> impl Fn(F) -> F {
+    move |d_sqrd: F| if d_sqrd < min_dist_sqrd { F::ZERO } else { F::ONE / d_sqrd.powf(F::from_f64(3.)) }
+}
+
+/// Computes the acceleration of every body on a `size` by `size` mesh over the x and y axes.
+/// Fails if `size` is not a power of two or the box is empty along x or y.
+/// Results are stored in `sim.a`.
+pub fn compute_acc_pm<V: Vector>(sim: &mut NBodySimulation<V>, size: usize, boundary: MeshBoundary) -> Result<(), NBodyError> {
+    let min_dist_sqrd: V::F = sim.config.min_dist_sqrd;
+    compute_acc_mesh(sim, size, boundary, pm_kernel(min_dist_sqrd))
+}
+
+/// Particle-mesh force calculation with cloud-in-cell assignment and an FFT convolution.
+/// Suited to large, smooth distributions where forces vary slowly between cells.
+///
+/// The transformed kernel is kept between calls and rebuilt when the size, boundary,
+/// bounds or `min_dist` change, so each call only transforms the density.
+#[derive(Debug)]
+pub struct PmSolver<F: Float = Scalar> {
+    /// Number of cells along each axis, a power of two.
+    pub size: usize,
+    pub boundary: MeshBoundary,
+    /// Transformed kernel with the `min_dist_sqrd` it was built for.
+    kernel: Mutex<Option<(F, MeshKernel<F>)>>,
+}
+
+impl<F: Float> PmSolver<F> {
+    /// Constructs a solver, checking that `size` is a power of two.
+    pub fn new(size: usize, boundary: MeshBoundary) -> Result<Self, NBodyError> {
+        check_mesh_size(size)?;
+        Ok(Self { size, boundary, kernel: Mutex::new(None) })
+    }
+}
+
+impl<F: Float> Clone for PmSolver<F> {
+    fn clone(&self) -> Self {
+        Self { size: self.size, boundary: self.boundary, kernel: Mutex::new(None) }
+    }
+}
+
+impl<V: Vector> Solver<V> for PmSolver<V::F> {
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError> {
+        let min_dist_sqrd: V::F = sim.config.min_dist_sqrd;
+        let mut cache = self.kernel.lock().unwrap_or_else(|e| e.into_inner());
+        let fits: bool = match &*cache {
+            Some((d, kernel)) => *d == min_dist_sqrd && kernel.fits(&sim.config, self.size, self.boundary),
+            None => false,
+        };
+        if !fits {
+            // Drop the old kernel first, so a failed rebuild doesn't leave it behind
+            *cache = None;
+            let kernel = MeshKernel::new(&sim.config, self.size, self.boundary, pm_kernel(min_dist_sqrd))?;
+            *cache = Some((min_dist_sqrd, kernel));
+        }
+        if let Some((_, kernel)) = &*cache {
+            kernel.apply(sim);
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "particle-mesh"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector2D};
+    use crate::nbody::{NBodyConfig2D, NBodySimulation2D, MovingBody2D, BodyKind, NBodyError, Solver, compute_acc_direct};
+    use super::{MeshBoundary, PmSolver, compute_acc_pm};
+
+    #[test]
+    fn test_pm() {
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(256., 256.,);
+        let config = NBodyConfig2D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, config);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(129., 129.), v: Vector2D::zero(), m: 5e6 }, BodyKind::Star);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(200., 129.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(90., 80.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+
+        // Inside the box the mesh matches the all-pairs result
+        compute_acc_direct(&mut sim);
+        let reference: Vec<Vector2D> = sim.a.clone();
+        compute_acc_pm(&mut sim, 128, MeshBoundary::Isolated).unwrap();
+        for (a, a_ref) in sim.a.iter().zip(&reference).skip(1) {
+            assert!((*a - *a_ref).norm() < 0.05 * a_ref.norm());
+        }
+
+        // Bodies near opposite walls only attract through a periodic mesh
+        sim.r[0] = Vector2D::from_xy(240., 129.);
+        sim.r[1] = Vector2D::from_xy(20., 129.);
+        compute_acc_pm(&mut sim, 128, MeshBoundary::Periodic).unwrap();
+        let periodic: Vector2D = sim.a[1];
+        assert!(periodic.x < 0.);
+        compute_acc_pm(&mut sim, 128, MeshBoundary::Isolated).unwrap();
+        assert!(sim.a[1].norm() < 1e-2 * periodic.norm());
+
+        // On an isolated mesh a body outside the box is left out instead of wrapping to the far side
+        sim.r[0] = Vector2D::from_xy(-20., 129.);
+        sim.r[1] = Vector2D::from_xy(240., 129.);
+        compute_acc_pm(&mut sim, 128, MeshBoundary::Isolated).unwrap();
+        assert_eq!(sim.a[0], Vector2D::zero());
+        assert!(sim.a[1].norm() < 1e-6);
+
+        // Sizes the FFT can't handle are rejected
+        assert_eq!(compute_acc_pm(&mut sim, 100, MeshBoundary::Periodic), Err(NBodyError::InvalidMeshSize(100)));
+        assert_eq!(PmSolver::<Scalar>::new(0, MeshBoundary::Isolated).unwrap_err(), NBodyError::InvalidMeshSize(0));
+
+        // So are boxes without cells to put the bodies in
+        sim.config.max_r = Vector2D::from_xy(256., 0.);
+        let empty = NBodyError::InvalidMeshBounds { axis: 1, min: 0., max: 0. };
+        assert_eq!(compute_acc_pm(&mut sim, 128, MeshBoundary::Periodic), Err(empty.clone()));
+        assert_eq!(PmSolver::new(128, MeshBoundary::Periodic).unwrap().compute_acc(&mut sim), Err(empty));
+    }
+
+    #[test]
+    fn test_pm_solver() {
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(256., 256.,);
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, NBodyConfig2D::new(10., min_r, max_r));
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(129., 129.), v: Vector2D::zero(), m: 5e6 }, BodyKind::Star);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(200., 129.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+
+        // The cached kernel gives the same result as building it on every call
+        let mut solver = PmSolver::new(64, MeshBoundary::Periodic).unwrap();
+        for _ in 0..2 {
+            compute_acc_pm(&mut sim, 64, MeshBoundary::Periodic).unwrap();
+            let expected: Vec<Vector2D> = sim.a.clone();
+            solver.compute_acc(&mut sim).unwrap();
+            assert_eq!(sim.a, expected);
+            sim.r[1].x -= 20.;
+        }
+
+        // Changing the bounds, min_dist or mesh rebuilds it
+        sim.config.max_r = Vector2D::from_xy(512., 256.);
+        compute_acc_pm(&mut sim, 64, MeshBoundary::Periodic).unwrap();
+        let expected: Vec<Vector2D> = sim.a.clone();
+        solver.compute_acc(&mut sim).unwrap();
+        assert_eq!(sim.a, expected);
+        sim.config.min_dist_sqrd = 1e4;
+        compute_acc_pm(&mut sim, 64, MeshBoundary::Periodic).unwrap();
+        assert_ne!(sim.a, expected);
+        let expected: Vec<Vector2D> = sim.a.clone();
+        solver.compute_acc(&mut sim).unwrap();
+        assert_eq!(sim.a, expected);
+        solver.boundary = MeshBoundary::Isolated;
+        compute_acc_pm(&mut sim, 64, MeshBoundary::Isolated).unwrap();
+        let expected: Vec<Vector2D> = sim.a.clone();
+        solver.compute_acc(&mut sim).unwrap();
+        assert_eq!(sim.a, expected);
+    }
+
+    #[test]
+    fn test_pm_zero_min_dist() {
+        // Without a min_dist the kernel is infinite at zero offset, which must not reach the mesh
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(256., 256.,);
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, NBodyConfig2D::new(0., min_r, max_r));
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(100., 100.), v: Vector2D::zero(), m: 5e6 }, BodyKind::Star);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(140., 100.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+        compute_acc_pm(&mut sim, 64, MeshBoundary::Periodic).unwrap();
+        assert!(sim.a.iter().all(|a| a.x.is_finite() && a.y.is_finite()));
+        assert!(sim.a[1].x < 0.);
+    }
+}
@@ -0,0 +1,89 @@
+//! Seedable random number generator owned by the simulation
+use rand::{Error, RngCore, SeedableRng};
+
+/// SplitMix64 generator.
+/// The whole state is a single `u64`, so it can be saved and restored exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    /// Restores a generator from a state returned by `state`.
+    pub fn from_state(state: u64) -> Self {
+        Self { state }
+    }
+
+    /// Gets the current state of the generator.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z: u64 = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for SimRng {
+    type Seed = [u8; 8];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Self::from_state(u64::from_le_bytes(seed))
+    }
+
+    fn seed_from_u64(seed: u64) -> Self {
+        Self::from_state(seed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfigBuilder, NBodySimulation3D, MovingBody3D, generate_galaxy, nbody_direct};
+
+    fn seeded_galaxy(seed: u64) -> NBodySimulation3D {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(seed).build().unwrap();
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(50, config);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+        for _ in 0..20 {
+            nbody_direct(&mut sim, 0.1).unwrap();
+        }
+        sim
+    }
+
+    #[test]
+    fn test_seeded_runs_match() {
+        let a = seeded_galaxy(7);
+        let b = seeded_galaxy(7);
+        let c = seeded_galaxy(8);
+        assert_eq!(a.r, b.r);
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.rng, b.rng);
+        assert_ne!(a.r, c.r);
+    }
+}
@@ -0,0 +1,194 @@
+//! Drivers that advance a simulation over many steps
+use super::{NBodySimulation};
+use super::barnes_hut::{nbody_barnes_hut, nbody_barnes_hut_adaptive};
+use super::block::{BlockTimestepConfig, nbody_block_hermite};
+use super::direct::{nbody_direct};
+use super::error::{NBodyError};
+use super::hermite::{nbody_hermite};
+use super::solver::{Solver, nbody_step};
+use super::timestep::{AdaptiveTimestep};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Integrator and force calculation used for each step of a run.
+/// Any other `Solver` can be run with `run_steps_with` and `run_until_with`.
+#[derive(Debug, Clone, Copy)]
+pub enum StepMethod<F: Float = Scalar> {
+    /// `nbody_direct` with a fixed timestep.
+    Direct { dt: F },
+    /// `nbody_barnes_hut` with a fixed timestep.
+    BarnesHut { dt: F, theta: F },
+    /// `nbody_barnes_hut_adaptive`.
+    BarnesHutAdaptive { timestep: AdaptiveTimestep<F>, theta: F },
+    /// `nbody_hermite` with a fixed timestep.
+    Hermite { dt: F },
+    /// `nbody_block_hermite`, advancing by `dt_max` each step.
+    BlockHermite(BlockTimestepConfig<F>),
+}
+
+impl<F: Float> StepMethod<F> {
+    /// Limits the timestep of the method to at most `dt`.
+    fn limited(self, dt: F) -> Self {
+        match self {
+            StepMethod::Direct { dt: dt0 } => StepMethod::Direct { dt: dt0.min(dt) },
+            StepMethod::BarnesHut { dt: dt0, theta } => StepMethod::BarnesHut { dt: dt0.min(dt), theta },
+            StepMethod::BarnesHutAdaptive { mut timestep, theta } => {
+                timestep.dt_max = timestep.dt_max.min(dt);
+                timestep.dt_min = timestep.dt_min.min(dt);
+                StepMethod::BarnesHutAdaptive { timestep, theta }
+            },
+            StepMethod::Hermite { dt: dt0 } => StepMethod::Hermite { dt: dt0.min(dt) },
+            StepMethod::BlockHermite(mut config) => {
+                config.dt_max = config.dt_max.min(dt);
+                StepMethod::BlockHermite(config)
+            },
+        }
+    }
+
+    /// Runs a single step and returns the timestep taken.
+    pub fn step<V: Vector<F = F>>(&self, sim: &mut NBodySimulation<V>) -> Result<F, NBodyError> {
+        match self {
+            StepMethod::Direct { dt } => nbody_direct(sim, *dt).map(|_| *dt),
+            StepMethod::BarnesHut { dt, theta } => nbody_barnes_hut(sim, *dt, *theta).map(|_| *dt),
+            StepMethod::BarnesHutAdaptive { timestep, theta } => nbody_barnes_hut_adaptive(sim, timestep, *theta),
+            StepMethod::Hermite { dt } => nbody_hermite(sim, *dt).map(|_| *dt),
+            StepMethod::BlockHermite(config) => nbody_block_hermite(sim, config).map(|_| config.dt_max),
+        }
+    }
+}
+
+/// Summary of a single step of a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepSummary<F: Float = Scalar> {
+    /// Number of the step, counting from the start of the simulation.
+    pub step: u64,
+    /// Simulation time at the end of the step.
+    pub t: F,
+    pub dt: F,
+    /// Number of bodies at the end of the step.
+    pub n: usize,
+    /// Number of mergers during the step.
+    pub mergers: usize,
+}
+
+impl<V: Vector> NBodySimulation<V> {
+    /// Runs one step, which returns the timestep taken, and summarizes it.
+    fn run_step(&mut self, step: impl FnOnce(&mut Self) -> Result<V::F, NBodyError>) -> Result<StepSummary<V::F>, NBodyError> {
+        let mergers: usize = self.mergers.len();
+        let dt: V::F = step(self)?;
+        Ok(StepSummary {
+            step: self.step,
+            t: self.t,
+            dt,
+            n: self.n,
+            mergers: self.mergers.len() - mergers,
+        })
+    }
+
+    /// Runs `k` steps.
+    pub fn run_steps(&mut self, k: usize, method: StepMethod<V::F>) -> Result<Vec<StepSummary<V::F>>, NBodyError> {
+        (0..k).map(|_| self.run_step(|sim| method.step(sim))).collect()
+    }
+
+    /// Runs `k` steps of `nbody_step` with the given solver and a fixed timestep.
+    pub fn run_steps_with(&mut self, k: usize, solver: &dyn Solver<V>, dt: V::F) -> Result<Vec<StepSummary<V::F>>, NBodyError> {
+        (0..k).map(|_| self.run_step(|sim| nbody_step(sim, solver, dt).map(|_| dt))).collect()
+    }
+
+    /// Runs until the simulation time reaches `t_end`.
+    /// The last step is shortened so the run ends at `t_end`.
+    /// Fails if `t_end` is not finite or a step doesn't advance the time, which would never end.
+    pub fn run_until(&mut self, t_end: V::F, method: StepMethod<V::F>) -> Result<Vec<StepSummary<V::F>>, NBodyError> {
+        self.run_until_by(t_end, |sim, remaining| method.limited(remaining).step(sim))
+    }
+
+    /// Runs `nbody_step` with the given solver and a fixed timestep until the simulation time reaches `t_end`,
+    /// like `run_until`.
+    pub fn run_until_with(&mut self, t_end: V::F, solver: &dyn Solver<V>, dt: V::F) -> Result<Vec<StepSummary<V::F>>, NBodyError> {
+        self.run_until_by(t_end, |sim, remaining| {
+            let dt: V::F = dt.min(remaining);
+            nbody_step(sim, solver, dt).map(|_| dt)
+        })
+    }
+
+    /// Runs steps, each limited to the remaining time, until the simulation time reaches `t_end`.
+    fn run_until_by<S>(&mut self, t_end: V::F, mut step: S) -> Result<Vec<StepSummary<V::F>>, NBodyError>
+        where S: FnMut(&mut Self, V::F) -> Result<V::F, NBodyError> {
+        if !t_end.is_finite() {
+            return Err(NBodyError::InvalidEndTime(t_end.to_f64()));
+        }
+        let mut summaries: Vec<StepSummary<V::F>> = Vec::new();
+        loop {
+            // Stop once the remaining time is lost in rounding
+            let remaining: V::F = t_end - self.t;
+            if remaining <= V::F::EPSILON * t_end.abs() { break }
+            let t: V::F = self.t;
+            let summary = self.run_step(|sim| step(sim, remaining))?;
+            if self.t.is_nan() || self.t <= t {
+                return Err(NBodyError::StalledStep { t: t.to_f64(), dt: summary.dt.to_f64() });
+            }
+            summaries.push(summary);
+        }
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, NBodyError, MeshBoundary, PmSolver, SymmetricDirectSolver};
+    use super::{StepMethod};
+
+    #[test]
+    fn test_run() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(400., 250.), v: Vector3D::from_xy(0., 0.1), m: 1. });
+        sim.kind[0] = BodyKind::BlackHole;
+
+        let summaries = sim.run_steps(3, StepMethod::Direct { dt: 0.5 }).unwrap();
+        assert_eq!(summaries.len(), 3);
+        assert_eq!(summaries[2].step, 3);
+        assert_eq!(summaries[2].t, 1.5);
+
+        // The last step is shortened to end exactly at t_end
+        let summaries = sim.run_until(2.7, StepMethod::BarnesHut { dt: 0.5, theta: 1. }).unwrap();
+        assert_eq!(summaries.len(), 3);
+        assert!((summaries[2].dt - 0.2).abs() < 1e-6);
+        assert!((sim.t - 2.7).abs() < 1e-6);
+        assert_eq!(sim.step, 6);
+
+        // Runs that could never end are rejected
+        let method = StepMethod::Direct { dt: 0.5 };
+        assert_eq!(sim.run_until(Scalar::INFINITY, method).unwrap_err(), NBodyError::InvalidEndTime(f64::INFINITY));
+        let stalled = NBodyError::StalledStep { t: sim.t as f64, dt: 0. };
+        assert_eq!(sim.run_until(5., StepMethod::Direct { dt: 0. }).unwrap_err(), stalled);
+    }
+
+    #[test]
+    fn test_run_solver() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(400., 250.), v: Vector3D::from_xy(0., 0.1), m: 1. });
+
+        // Solvers outside of StepMethod drive the same runs
+        let summaries = sim.run_steps_with(2, &SymmetricDirectSolver, 0.5).unwrap();
+        assert_eq!(summaries[1].step, 2);
+        let summaries = sim.run_until_with(2.7, &SymmetricDirectSolver, 0.5).unwrap();
+        assert_eq!(summaries.len(), 4);
+        assert!((summaries[3].dt - 0.2).abs() < 1e-6);
+        assert!((sim.t - 2.7).abs() < 1e-6);
+        assert!(sim.r[1].x < 400.);
+
+        // Solver errors end the run
+        let solver = PmSolver::new(64, MeshBoundary::Isolated).unwrap();
+        sim.config.max_r = Vector3D::from_xy(500., 0.);
+        let empty = NBodyError::InvalidMeshBounds { axis: 1, min: 0., max: 0. };
+        assert_eq!(sim.run_until_with(5., &solver, 0.5).unwrap_err(), empty);
+    }
+}
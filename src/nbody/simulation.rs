@@ -1,55 +1,153 @@
 //! Module for defining simulation of bodies (planets, etc.)
-use rand::Rng;
-use super::bodies::{Scalar, Vector, Vector3D, MovingBody};
+use std::collections::HashMap;
+use super::bodies::{Vector, Vector2D, Vector3D, MovingBody, BodyKind};
+use crate::vector::{Float};
 use super::generators::{generate_satellite};
+use super::blackholes::{BlackHoleMergerConfig, merge_blackholes};
+use super::boundary::{BoundaryPolicy, apply_boundary};
+use super::accretion::{AccretionRecord, accrete};
+use super::motion::{Motion};
+use super::collisions::{CollisionMode, MergerEvent, merge_collisions};
+use super::error::{NBodyError};
+use super::finite::{NonFinitePolicy, NonFiniteRecord, snapshot, check_finite};
+use super::rng::{SimRng};
+use super::events::{Event, Observers};
+use rand::SeedableRng;
 
 /// Class to configure a simulation
 #[derive(Debug)]
 pub struct NBodyConfig<V: Vector> {
-    pub min_dist: Scalar,
-    pub min_dist_sqrd: Scalar,
+    pub min_dist: V::F,
+    pub min_dist_sqrd: V::F,
     pub min_r: V,
     pub max_r: V,
-    pub num_blackholes: usize,
+    pub collision_mode: CollisionMode,
+    pub accretion_radius: V::F,
+    /// Black hole merger settings, or `None` (the default) to disable mergers.
+    pub blackhole_mergers: Option<BlackHoleMergerConfig<V::F>>,
+    pub boundary: BoundaryPolicy,
+    pub non_finite: NonFinitePolicy,
+    /// Seed of the simulation's random number generator, or `None` to seed from entropy.
+    pub seed: Option<u64>,
 }
 
 impl<V: Vector> NBodyConfig<V> {
-    pub fn new(min_dist: Scalar, min_r: V, max_r: V) -> Self {
+    pub fn new(min_dist: V::F, min_r: V, max_r: V) -> Self {
         Self {
             min_dist,
             min_dist_sqrd: min_dist * min_dist,
             min_r,
             max_r,
-            num_blackholes: 0,
+            collision_mode: CollisionMode::Respawn,
+            accretion_radius: min_dist,
+            blackhole_mergers: None,
+            boundary: BoundaryPolicy::Respawn,
+            non_finite: NonFinitePolicy::Remove,
+            seed: None,
         }
     }
 }
 
+pub type NBodyConfig2D = NBodyConfig<Vector2D>;
 pub type NBodyConfig3D = NBodyConfig<Vector3D>;
 
+/// Positions and masses of the sources of gravity, gathered for the force solvers.
+/// Tracers are left out, so they add nothing to tree construction or to the direct sums.
+#[derive(Debug, Clone)]
+pub struct SourceBuffer<V: Vector> {
+    /// Indices of the sources in the simulation.
+    pub index: Vec<usize>,
+    pub r: Vec<V>,
+    pub m: Vec<V::F>,
+}
+
+impl<V: Vector> Default for SourceBuffer<V> {
+    fn default() -> Self {
+        Self { index: Vec::new(), r: Vec::new(), m: Vec::new() }
+    }
+}
+
+impl<V: Vector> SourceBuffer<V> {
+    /// Refills the buffer with the sources of `sim`, reusing its allocations.
+    pub fn gather(&mut self, sim: &NBodySimulation<V>) {
+        self.index.clear();
+        self.r.clear();
+        self.m.clear();
+        for i in (0..sim.n).filter(|&i| sim.kind[i] != BodyKind::Tracer) {
+            self.index.push(i);
+            self.r.push(sim.r[i]);
+            self.m.push(sim.m[i]);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
 /// Class defining the simulation for 2D n-body problem.
 #[derive(Debug)]
 pub struct NBodySimulation<V: Vector> {
     pub n: usize,
-    pub m: Vec<Scalar>,
+    pub m: Vec<V::F>,
     pub r: Vec<V>,
     pub v: Vec<V>,
     pub a: Vec<V>,
+    pub j: Vec<V>,
+    pub radius: Vec<V::F>,
+    pub kind: Vec<BodyKind>,
+    pub motion: Vec<Motion<V>>,
+    pub id: Vec<u64>,
+    pub t: V::F,
+    /// Number of completed steps.
+    pub step: u64,
+    pub mergers: Vec<MergerEvent<V>>,
+    pub accretion_history: Vec<Vec<AccretionRecord<V::F>>>,
+    pub non_finite_log: Vec<NonFiniteRecord<V::F>>,
+    /// Used by the generators and all respawn logic, so a seeded run is reproducible.
+    pub rng: SimRng,
+    pub observers: Observers<V>,
     pub config: NBodyConfig<V>,
+    next_id: u64,
+    index: HashMap<u64, usize>,
+    sources: SourceBuffer<V>,
 }
 
+pub type NBodySimulation2D = NBodySimulation<Vector2D>;
 pub type NBodySimulation3D = NBodySimulation<Vector3D>;
 
 impl<V: Vector> NBodySimulation<V> {
-    /// Constructs and empty `NBodySimulation2D` with n uninitialized bodies.
+    /// Constructs an empty simulation with n uninitialized stars.
     pub fn empty(n: usize, config: NBodyConfig<V>) -> Self {
         let sim: Self = Self{
             n,
-            m: vec![0.; n],
+            m: vec![V::F::ZERO; n],
             r: vec![V::zero(); n],
             v: vec![V::zero(); n],
             a: vec![V::zero(); n],
+            j: vec![V::zero(); n],
+            radius: vec![V::F::ZERO; n],
+            kind: vec![BodyKind::Star; n],
+            motion: vec![Motion::Free; n],
+            id: (0..n as u64).collect(),
+            t: V::F::ZERO,
+            step: 0,
+            mergers: Vec::new(),
+            accretion_history: vec![Vec::new(); n],
+            non_finite_log: Vec::new(),
+            rng: match config.seed {
+                Some(seed) => SimRng::seed_from_u64(seed),
+                None => SimRng::from_entropy(),
+            },
+            observers: Observers::new(),
             config,
+            next_id: n as u64,
+            index: (0..n).map(|i| (i as u64, i)).collect(),
+            sources: SourceBuffer::default(),
         };
         sim
     }
@@ -60,6 +158,10 @@ impl<V: Vector> NBodySimulation<V> {
         self.r[i] = body.r;
         self.v[i] = body.v;
         self.a[i] = V::zero();
+        self.j[i] = V::zero();
+        self.radius[i] = V::F::ZERO;
+        self.motion[i] = Motion::Free;
+        self.accretion_history[i].clear();
     }
 
     /// Gets a body from the simulation
@@ -71,58 +173,340 @@ impl<V: Vector> NBodySimulation<V> {
         }
     }
 
-    /// Resets a particle based on its type
+    /// Gets the current index of the body with the given id, if it is still in the simulation.
+    pub fn index_of(&self, id: u64) -> Option<usize> {
+        self.index.get(&id).copied()
+    }
+
+    /// Gets the id that the next new body will receive.
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Replaces the ids of all bodies and the next id to hand out, e.g. when restoring a checkpoint.
+    /// Fails without changing anything if an id is repeated or not below `next_id`.
+    pub(crate) fn restore_ids(&mut self, id: Vec<u64>, next_id: u64) -> Result<(), NBodyError> {
+        let index: HashMap<u64, usize> = id.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        if index.len() != id.len() {
+            return Err(NBodyError::InvalidCheckpoint("repeated body id".to_string()));
+        }
+        if id.iter().any(|&id| id >= next_id) {
+            return Err(NBodyError::InvalidCheckpoint(format!("body id is not below the next id {}", next_id)));
+        }
+        self.index = index;
+        self.id = id;
+        self.next_id = next_id;
+        Ok(())
+    }
+
+    /// Gives the body at index `i` a new id, marking it as a different body.
+    pub fn renew_id(&mut self, i: usize) {
+        self.index.remove(&self.id[i]);
+        self.id[i] = self.next_id;
+        self.index.insert(self.next_id, i);
+        self.next_id += 1;
+    }
+
+    /// Checks if a body is a black hole
+    pub fn is_blackhole(&self, i: usize) -> bool {
+        self.kind[i] == BodyKind::BlackHole
+    }
+
+    /// Checks whether a body follows a prescribed trajectory instead of moving freely.
+    pub fn is_constrained(&self, i: usize) -> bool {
+        !matches!(self.motion[i], Motion::Free)
+    }
+
+    /// Gets the position and velocity at time `t` of a body that is not moved by gravity,
+    /// or `None` if the body moves freely.
+    pub fn constrained_state(&self, i: usize, t: V::F) -> Option<(V, V)> {
+        match &self.motion[i] {
+            Motion::Free => None,
+            Motion::Pinned => Some((self.r[i], V::zero())),
+            Motion::Scripted(path) => Some((path.position(t), path.velocity(t))),
+        }
+    }
+
+    /// Gets the indices of all bodies of the given kind
+    pub fn indices_of(&self, kind: BodyKind) -> Vec<usize> {
+        (0..self.n).filter(|&i| self.kind[i] == kind).collect()
+    }
+
+    /// Gets the indices of all black holes
+    pub fn blackholes(&self) -> Vec<usize> {
+        self.indices_of(BodyKind::BlackHole)
+    }
+
+    /// Gets the indices of all bodies that are sources of gravity, i.e. all but tracers
+    pub fn sources(&self) -> Vec<usize> {
+        (0..self.n).filter(|&i| self.kind[i] != BodyKind::Tracer).collect()
+    }
+
+    /// Gathers the sources into a buffer kept by the simulation and calls `f` with it.
+    /// The buffer is reused, so repeated force evaluations don't allocate once it has grown to fit.
+    pub fn with_sources<R>(&mut self, f: impl FnOnce(&mut Self, &SourceBuffer<V>) -> R) -> R {
+        let mut sources: SourceBuffer<V> = std::mem::take(&mut self.sources);
+        sources.gather(self);
+        let result: R = f(self, &sources);
+        self.sources = sources;
+        result
+    }
+
+    /// Counts the black holes in the simulation
+    pub fn num_blackholes(&self) -> usize {
+        self.kind.iter().filter(|&&kind| kind == BodyKind::BlackHole).count()
+    }
+
+    /// Gets the total mass a body has accreted
+    pub fn accreted_mass(&self, i: usize) -> V::F {
+        self.accretion_history[i].iter().map(|record| record.m_accreted).sum()
+    }
+
+    /// Swaps two bodies in the simulation
+    pub fn swap(&mut self, i: usize, k: usize) {
+        self.m.swap(i, k);
+        self.r.swap(i, k);
+        self.v.swap(i, k);
+        self.a.swap(i, k);
+        self.j.swap(i, k);
+        self.radius.swap(i, k);
+        self.kind.swap(i, k);
+        self.motion.swap(i, k);
+        self.accretion_history.swap(i, k);
+        self.id.swap(i, k);
+        self.index.insert(self.id[i], i);
+        self.index.insert(self.id[k], k);
+    }
+
+    /// Adds a body of the given kind to the end of the simulation and returns its index.
+    pub fn add_body(&mut self, body: &MovingBody<V>, kind: BodyKind) -> usize {
+        self.m.push(V::F::ZERO);
+        self.r.push(V::zero());
+        self.v.push(V::zero());
+        self.a.push(V::zero());
+        self.j.push(V::zero());
+        self.radius.push(V::F::ZERO);
+        self.kind.push(kind);
+        self.motion.push(Motion::Free);
+        self.accretion_history.push(Vec::new());
+        self.id.push(self.next_id);
+        self.index.insert(self.next_id, self.n);
+        self.next_id += 1;
+        self.n += 1;
+        self.set(self.n - 1, body);
+        self.n - 1
+    }
+
+    /// Adds a black hole to the end of the simulation and returns its index.
+    pub fn add_blackhole(&mut self, body: &MovingBody<V>) -> usize {
+        self.add_body(body, BodyKind::BlackHole)
+    }
+
+    /// Removes a body from the simulation.
+    /// The last body is moved to fill the gap.
+    pub fn remove_body(&mut self, i: usize) {
+        self.swap(i, self.n - 1);
+        self.m.pop();
+        self.r.pop();
+        self.v.pop();
+        self.a.pop();
+        self.j.pop();
+        self.radius.pop();
+        self.kind.pop();
+        self.motion.pop();
+        self.accretion_history.pop();
+        if let Some(id) = self.id.pop() {
+            self.index.remove(&id);
+        }
+        self.n -= 1;
+    }
+
+    /// Replaces a body with a new satellite orbiting body `ci`.
+    /// Black holes are replaced by stars, and tracers stay massless.
+    /// The new satellite is a different body, so it receives a new id.
     pub fn reset(&mut self, i: usize, ci: usize) {
         let c = self.get(ci);
-        // If resetting a black hole, delete it
-        if i < self.config.num_blackholes {
-            // Reduce size of black hole portion of array
-            self.config.num_blackholes -= 1;
-            
-            // Move the previous last black hole to the deleted black hole's position
-            self.set(i, &self.get(self.config.num_blackholes));
-
-            // Replace the last black hole with a satellite
-            self.set(self.config.num_blackholes, &generate_satellite(&c))
-        } else {
-            // Otherwise, repalce this star with a new satellite
-            self.set(i, &generate_satellite(&c));
+        let body = generate_satellite(&c, &mut self.rng);
+        self.set(i, &body);
+        match self.kind[i] {
+            BodyKind::BlackHole => self.kind[i] = BodyKind::Star,
+            BodyKind::Tracer => self.m[i] = V::F::ZERO,
+            _ => (),
         }
+        let id = self.id[i];
+        self.renew_id(i);
+        self.emit(Event::Reset { id, new_id: self.id[i], center: self.id[ci] });
     }
-    /// Integrate velocity and position over time
-    pub fn integrate(&mut self, dt: Scalar) {
-        let mut rng = rand::thread_rng();
 
+    /// Integrate velocity and position over time.
+    /// Non-finite bodies are handled according to `config.non_finite`.
+    pub fn integrate(&mut self, dt: V::F) -> Result<(), NBodyError> {
+        let snapshot = snapshot(self);
         for i in 0..self.n {
+            // Pinned and scripted bodies ignore forces
+            if let Some((r, v)) = self.constrained_state(i, self.t + dt) {
+                self.r[i] = r;
+                self.v[i] = v;
+                continue;
+            }
+
             // Update velocities
             self.v[i] += self.a[i] * dt;
             
             // Update positions
             self.r[i] += self.v[i] * dt;
+        }
+        self.t += dt;
+        check_finite(self, snapshot)?;
+        self.resolve_encounters();
+        Ok(())
+    }
 
-            // Check for black hole intersections
-            for ci in 0..self.config.num_blackholes {
-                // Don't check for inteserctions against self
-                if i == ci { continue };
+    /// Resolves close encounters between bodies and resets bodies that left the simulation bounds.
+    /// Called by every integrator once positions have been advanced.
+    pub fn resolve_encounters(&mut self) {
+        if let Some(mergers) = self.config.blackhole_mergers {
+            merge_blackholes(self, &mergers);
+        }
+        match self.config.collision_mode {
+            CollisionMode::Respawn => self.respawn_near_blackholes(),
+            CollisionMode::Merge => merge_collisions(self),
+            CollisionMode::Accrete => accrete(self),
+        }
+
+        apply_boundary(self);
+    }
+
+    /// Resets bodies that came within `min_dist` of a black hole.
+    /// Constrained bodies keep their trajectory and are never reset.
+    fn respawn_near_blackholes(&mut self) {
+        let blackholes: Vec<usize> = self.blackholes();
+        for i in 0..self.n {
+            if self.is_constrained(i) { continue }
+            for &ci in &blackholes {
+                // Don't check for inteserctions against self, or against black holes reset earlier
+                if i == ci || !self.is_blackhole(ci) { continue };
 
                 let c = self.get(ci);
                 let d = c.r - self.r[i];
-                let d_sqrd: Scalar = d.l2_sqrd();
+                let d_sqrd: V::F = d.l2_sqrd();
 
                 if d_sqrd < self.config.min_dist_sqrd {
                     self.reset(i, ci);
                 }
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Float, Vector, Vector3D};
+    use crate::nbody::{NBodyConfig, NBodyConfig3D, NBodySimulation, NBodySimulation3D, MovingBody, MovingBody3D, BodyKind, nbody_barnes_hut};
+    use crate::nbody::{generate_galaxy, generate_tracers, compute_acc_barnes_hut};
+
+    #[test]
+    fn test_add_remove() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(0, config);
+        let body = |x, m| MovingBody3D { r: Vector3D::from_xy(x, 0.), v: Vector3D::zero(), m };
+
+        assert_eq!(sim.add_body(&body(1., 1.), BodyKind::Star), 0);
+        assert_eq!(sim.add_body(&body(2., 2.), BodyKind::Gas), 1);
+        assert_eq!(sim.add_blackhole(&body(3., 3.)), 2);
+        assert_eq!(sim.add_blackhole(&body(4., 4.)), 3);
+        assert_eq!(sim.n, 4);
+        assert_eq!(sim.num_blackholes(), 2);
+        assert_eq!(sim.blackholes(), vec![2, 3]);
+        assert_eq!(sim.indices_of(BodyKind::Gas), vec![1]);
+        assert_eq!(sim.m, vec![1., 2., 3., 4.]);
+
+        // The last body fills the gap and keeps its kind
+        sim.remove_body(2);
+        assert_eq!(sim.n, 3);
+        assert_eq!(sim.m, vec![1., 2., 4.]);
+        assert_eq!(sim.blackholes(), vec![2]);
+
+        sim.remove_body(0);
+        assert_eq!(sim.m, vec![4., 2.]);
+        assert_eq!(sim.kind, vec![BodyKind::BlackHole, BodyKind::Gas]);
+        assert_eq!(sim.r.len(), 2);
+    }
 
-            // Check for out of bounds
-            if !self.r[i].in_bounds(&self.config.min_r, &self.config.max_r) {
-                // Don't reset if there are no black holes
-                if self.config.num_blackholes > 0 { continue }
+    #[test]
+    fn test_ids() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        let body = |x, m| MovingBody3D { r: Vector3D::from_xy(x, 0.), v: Vector3D::zero(), m };
+        sim.set(0, &body(1., 1.));
+        sim.set(1, &body(2., 2.));
 
-                // Pick a random black hold to put next to
-                let ci = rng.gen_range(0, self.config.num_blackholes);
-                self.reset(i, ci);
+        let bh = sim.add_blackhole(&body(3., 3.));
+        let bh_id = sim.id[bh];
+        assert_eq!(sim.id, vec![0, 1, 2]);
+
+        // Ids follow their bodies when they are moved by a removal
+        sim.remove_body(1);
+        assert_eq!(sim.index_of(1), None);
+        assert_eq!(sim.m[sim.index_of(0).unwrap()], 1.);
+        assert_eq!(sim.m[sim.index_of(bh_id).unwrap()], 3.);
+        for (i, &id) in sim.id.iter().enumerate() {
+            assert_eq!(sim.index_of(id), Some(i));
+        }
+
+        // Respawned bodies are new bodies
+        sim.reset(0, 1);
+        assert_eq!(sim.index_of(0), None);
+        assert_eq!(sim.index_of(3), Some(0));
+    }
+
+    #[test]
+    fn test_precision() {
+        // The same simulation in single and double precision
+        fn run<V: Vector>(sim: &mut NBodySimulation<V>, step: impl Fn(&mut NBodySimulation<V>)) -> (f64, f64) {
+            let f = |x: f64| V::F::from_f64(x);
+            sim.set(0, &MovingBody { r: V::from_xy(f(250.), f(250.)), v: V::zero(), m: f(5e6) });
+            sim.set(1, &MovingBody { r: V::from_xy(f(300.), f(250.)), v: V::from_xy(f(0.), f(0.5)), m: f(1e-2) });
+            sim.kind[0] = BodyKind::BlackHole;
+            for _ in 0..100 {
+                step(sim);
             }
+            let (x, y) = sim.r[1].to_xy();
+            (x.to_f64(), y.to_f64())
         }
+
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let mut sim32: NBodySimulation3D = NBodySimulation3D::empty(2, NBodyConfig3D::new(10., min_r, max_r));
+        let min_r: Vector3D<f64> = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D<f64> = Vector3D::from_xy(500., 500.,);
+        let mut sim64: NBodySimulation<Vector3D<f64>> = NBodySimulation::empty(2, NBodyConfig::new(10., min_r, max_r));
+
+        let (x32, y32) = run(&mut sim32, |sim| nbody_barnes_hut(sim, 0.1, 1.).unwrap());
+        let (x64, y64) = run(&mut sim64, |sim| nbody_barnes_hut(sim, 0.1, 1.).unwrap());
+        assert!((x32 - x64).abs() < 1e-2 && (y32 - y64).abs() < 1e-2, "({}, {}) != ({}, {})", x32, y32, x64, y64);
+    }
+
+    #[test]
+    fn test_source_buffer() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(20, config);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+        generate_tracers(&mut sim, &c, 1000);
+
+        // Only the 20 sources are gathered, into the same buffer on every evaluation
+        compute_acc_barnes_hut(&mut sim, 0.5);
+        assert_eq!(sim.sources.len(), 20);
+        let buffer: *const Vector3D = sim.sources.r.as_ptr();
+        compute_acc_barnes_hut(&mut sim, 0.5);
+        assert_eq!(sim.sources.r.as_ptr(), buffer);
+        assert_eq!(sim.sources.index, (0..20).collect::<Vec<usize>>());
     }
 }
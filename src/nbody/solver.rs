@@ -0,0 +1,110 @@
+//! Force solvers that can be swapped at runtime
+use super::{NBodySimulation};
+use super::barnes_hut::{compute_acc_barnes_hut};
+use super::direct::{compute_acc_direct, compute_acc_symmetric};
+use super::error::{NBodyError};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Computes the gravitational acceleration of every body, independently of how bodies are integrated.
+pub trait Solver<V: Vector> {
+    /// Computes the acceleration of every body and stores it in `sim.a`.
+    /// Fails if the solver's settings don't fit the simulation.
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError>;
+
+    /// Short name of the solver, e.g. for comparisons and logs.
+    fn name(&self) -> &'static str;
+}
+
+/// All-pairs force calculation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirectSolver;
+
+impl<V: Vector> Solver<V> for DirectSolver {
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError> {
+        compute_acc_direct(sim);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "direct"
+    }
+}
+
+/// All-pairs force calculation visiting each pair once, conserving momentum exactly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SymmetricDirectSolver;
+
+impl<V: Vector> Solver<V> for SymmetricDirectSolver {
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError> {
+        compute_acc_symmetric(sim);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "direct-symmetric"
+    }
+}
+
+/// Barnes-Hut tree force calculation with opening angle `theta`.
+#[derive(Debug, Clone, Copy)]
+pub struct BarnesHutSolver<F: Float = Scalar> {
+    pub theta: F,
+}
+
+impl<F: Float> BarnesHutSolver<F> {
+    pub fn new(theta: F) -> Self {
+        Self { theta }
+    }
+}
+
+impl<V: Vector> Solver<V> for BarnesHutSolver<V::F> {
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError> {
+        compute_acc_barnes_hut(sim, self.theta);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "barnes-hut"
+    }
+}
+
+/// Runs a single timestep of the simulation, computing accelerations with the given solver.
+pub fn nbody_step<V: Vector, S: Solver<V> + ?Sized>(sim: &mut NBodySimulation<V>, solver: &S, dt: V::F) -> Result<(), NBodyError> {
+    sim.begin_step();
+    solver.compute_acc(sim)?;
+    sim.integrate(dt)?;
+    sim.end_step();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Scalar, Vector, Vector3D};
+    use crate::nbody::{NBodyConfigBuilder, NBodySimulation3D, MovingBody3D, generate_galaxy};
+    use super::{Solver, DirectSolver, BarnesHutSolver, nbody_step};
+
+    #[test]
+    fn test_solvers() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(1).build().unwrap();
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(200, config);
+        let c = MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+
+        // Compare each solver against the all-pairs reference
+        let solvers: Vec<Box<dyn Solver<Vector3D>>> = vec![Box::new(DirectSolver), Box::new(BarnesHutSolver::new(0.5))];
+        DirectSolver.compute_acc(&mut sim).unwrap();
+        let reference: Vec<Vector3D> = sim.a.clone();
+        for solver in &solvers {
+            solver.compute_acc(&mut sim).unwrap();
+            let diff: Scalar = (0..sim.n).map(|i| (sim.a[i] - reference[i]).l2_sqrd()).sum();
+            let norm: Scalar = reference.iter().map(|a| a.l2_sqrd()).sum();
+            let err: Scalar = (diff / norm).sqrt();
+            assert!(err < 0.05, "{} differs from direct by {}", solver.name(), err);
+        }
+
+        nbody_step(&mut sim, solvers[1].as_ref(), 0.1).unwrap();
+        assert_eq!(sim.step, 1);
+    }
+}
@@ -0,0 +1,9 @@
+//! Helpers shared by the force solver tests
+use crate::vector::{Scalar, Vector};
+
+/// Relative RMS difference between two sets of accelerations.
+pub fn rms<V: Vector<F = Scalar>>(a: &[V], reference: &[V]) -> Scalar {
+    let diff: Scalar = a.iter().zip(reference).map(|(&a, &b)| (a - b).l2_sqrd()).sum();
+    let norm: Scalar = reference.iter().map(|a| a.l2_sqrd()).sum();
+    (diff / norm).sqrt()
+}
@@ -0,0 +1,82 @@
+//! Adaptive global timestep control
+use super::{NBodySimulation};
+use crate::vector::{Float, Scalar, Vector};
+
+/// Criterion used to choose the global timestep.
+/// Both use the softening length `min_dist` as the length scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestepCriterion {
+    /// `dt = eta * min(sqrt(min_dist / |a|))` over all bodies.
+    Acceleration,
+    /// `dt = eta * min(min_dist / |v|)` over all bodies.
+    Courant,
+}
+
+/// Configures adaptive global timesteps.
+/// The accuracy parameter `eta` scales the criterion, and the result is clamped to `[dt_min, dt_max]`.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveTimestep<F: Float = Scalar> {
+    pub criterion: TimestepCriterion,
+    pub eta: F,
+    pub dt_min: F,
+    pub dt_max: F,
+}
+
+impl<F: Float> AdaptiveTimestep<F> {
+    pub fn new(criterion: TimestepCriterion, eta: F, dt_min: F, dt_max: F) -> Self {
+        Self {
+            criterion,
+            eta,
+            dt_min,
+            dt_max,
+        }
+    }
+
+    /// Chooses the timestep for the current state of the simulation.
+    /// Accelerations must already be computed when using `TimestepCriterion::Acceleration`.
+    pub fn choose<V: Vector<F = F>>(&self, sim: &NBodySimulation<V>) -> F {
+        let eps: F = sim.config.min_dist;
+        let mut dt: F = self.dt_max;
+        for i in 0..sim.n {
+            let dt_i: F = match self.criterion {
+                TimestepCriterion::Acceleration => (eps / sim.a[i].l2_sqrd().sqrt()).sqrt(),
+                TimestepCriterion::Courant => eps / sim.v[i].l2_sqrd().sqrt(),
+            };
+            dt = dt.min(self.eta * dt_i);
+        }
+        dt.max(self.dt_min)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector::{Vector, Vector3D};
+    use crate::nbody::{NBodyConfig3D, NBodySimulation3D, MovingBody3D, BodyKind, nbody_barnes_hut_adaptive};
+    use super::{AdaptiveTimestep, TimestepCriterion};
+
+    #[test]
+    fn test_adaptive_timestep() {
+        let min_r: Vector3D = Vector3D::from_xy(0., 0.);
+        let max_r: Vector3D = Vector3D::from_xy(500., 500.,);
+        let config = NBodyConfig3D::new(10., min_r, max_r);
+        let mut sim: NBodySimulation3D = NBodySimulation3D::empty(2, config);
+        sim.set(0, &MovingBody3D { r: Vector3D::from_xy(250., 250.), v: Vector3D::zero(), m: 5e6 });
+        sim.set(1, &MovingBody3D { r: Vector3D::from_xy(400., 250.), v: Vector3D::from_xy(0., 1.), m: 1. });
+        sim.kind[0] = BodyKind::BlackHole;
+
+        // A far away body allows large steps
+        let timestep = AdaptiveTimestep::new(TimestepCriterion::Acceleration, 0.1, 1e-3, 1.);
+        let far_dt = nbody_barnes_hut_adaptive(&mut sim, &timestep, 1.).unwrap();
+
+        // A close passage forces smaller steps
+        sim.r[1] = Vector3D::from_xy(265., 250.);
+        let close_dt = nbody_barnes_hut_adaptive(&mut sim, &timestep, 1.).unwrap();
+        assert!(close_dt < far_dt);
+        assert!(close_dt >= timestep.dt_min);
+
+        // Courant steps follow the velocity
+        let courant = AdaptiveTimestep::new(TimestepCriterion::Courant, 0.5, 1e-3, 10.);
+        sim.v[1] = Vector3D::from_xy(0., 2.);
+        assert_eq!(courant.choose(&sim), 2.5);
+    }
+}
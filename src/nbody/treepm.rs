@@ -0,0 +1,306 @@
+//! TreePM force calculation: long-range forces from a mesh and short-range forces from a truncated tree walk
+use super::{NBodySimulation};
+use super::error::{NBodyError};
+use super::pm::{MeshBoundary, check_mesh_bounds, check_mesh_size, compute_acc_mesh};
+use super::solver::{Solver};
+use crate::vector::{Float, Scalar, Vector};
+use super::barnes_hut::{build_source_tree};
+
+/// Complementary error function, with an absolute error below 1.5e-7
+/// (Abramowitz and Stegun 7.1.26).
+pub fn erfc(x: f64) -> f64 {
+    if x < 0. { return 2. - erfc(-x) }
+    let t: f64 = 1. / (1. + 0.327_591_1 * x);
+    let poly: f64 = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    poly * (-x * x).exp()
+}
+
+/// Fraction of the pairwise force at separation `x = r / (2 r_s)` left to the tree.
+/// The mesh gets `erf(x)^6`, which cancels the `1 / r^5` singularity of the force
+/// so the long-range part is smooth, and the tree part falls off like `erfc(x)`.
+fn short_fraction(x: f64) -> f64 {
+    1. - (1. - erfc(x)).powi(6)
+}
+
+/// Long-range kernel `erf(x)^6 / r^6` at `x = r / (2 r_s)`, which stays finite as r goes to 0.
+fn long_kernel(x: f64, r_s: f64) -> f64 {
+    // erf(x) / x, using its series where the erfc approximation loses precision
+    let erf_x: f64 = if x < 0.1 {
+        let x2: f64 = x * x;
+        2. / std::f64::consts::PI.sqrt() * (1. - x2 / 3. + x2 * x2 / 10. - x2 * x2 * x2 / 42.)
+    } else {
+        (1. - erfc(x)) / x
+    };
+    erf_x.powi(6) / (2. * r_s).powi(6)
+}
+
+/// TreePM force calculation. Each pairwise force is split into a smooth long-range part,
+/// solved on a mesh, and a short-range part falling off like `erfc(r / (2 r_s))`,
+/// summed by a Barnes-Hut walk that ignores cells beyond `r_cut`.
+///
+/// The tree keeps the fraction `1 - erf(x)^6` of the force, which is `6 erfc(x)` to leading order.
+/// Keeping just `erfc(x)` would leave the mesh `erf(x) / r^6`, which is singular at r = 0
+/// for this force law and can't be resolved on a grid.
+///
+/// In a periodic box the mesh and the walk both include forces across the walls,
+/// which `nbody_barnes_hut` misses at any opening angle.
+#[derive(Debug, Clone, Copy)]
+pub struct TreePmSolver<F: Float = Scalar> {
+    /// Opening angle of the tree walk.
+    pub theta: F,
+    /// Number of mesh cells along each axis, a power of two.
+    pub size: usize,
+    pub boundary: MeshBoundary,
+    /// Split scale `r_s` in units of mesh cells, along the axis with the wider cells.
+    pub split: F,
+    /// Cutoff radius of the tree walk in units of `r_s`.
+    pub cut: F,
+}
+
+impl<F: Float> TreePmSolver<F> {
+    /// Constructs a solver with a split of 3 cells and a cutoff of 4.5 split scales,
+    /// so `size` must be at least 32.
+    /// The split is wider than the 1.25 cells usual for inverse-square gravity because
+    /// the steeper force leaves more of the long-range part varying within a cell.
+    pub fn new(theta: F, size: usize, boundary: MeshBoundary) -> Result<Self, NBodyError> {
+        let solver = Self {
+            theta,
+            size,
+            boundary,
+            split: F::from_f64(3.),
+            cut: F::from_f64(4.5),
+        };
+        solver.validate()?;
+        Ok(solver)
+    }
+
+    /// Checks the mesh size, and that the cutoff is positive and below half the mesh,
+    /// so the walk only ever needs the nearest periodic image of a cell.
+    pub fn validate(&self) -> Result<(), NBodyError> {
+        check_mesh_size(self.size)?;
+        let cutoff: F = self.cut * self.split;
+        let half: F = F::from_f64(self.size as f64 / 2.);
+        if !cutoff.is_finite() || self.cut <= F::ZERO || self.split <= F::ZERO || cutoff >= half {
+            return Err(NBodyError::InvalidTreePmCutoff(cutoff.to_f64()));
+        }
+        Ok(())
+    }
+}
+
+/// Distance from `x` to the interval [lo, hi], wrapping around `period` if given.
+fn axis_gap<F: Float>(x: F, lo: F, hi: F, period: Option<F>) -> F {
+    let gap = |x: F| (lo - x).max(x - hi).max(F::ZERO);
+    match period {
+        Some(p) => gap(x).min(gap(x - p)).min(gap(x + p)),
+        None => gap(x),
+    }
+}
+
+/// Wraps an offset to the nearest periodic image.
+fn nearest_image<F: Float>(d: F, period: Option<F>) -> F {
+    match period {
+        Some(p) => d - p * (d / p + F::from_f64(0.5)).floor(),
+        None => d,
+    }
+}
+
+/// Computes the acceleration of every body with the TreePM split.
+/// Fails if the solver is invalid, `min_dist` is not positive or the mesh bounds have no width,
+/// or if in a periodic box the cutoff reaches half of the shorter axis. Results are stored in `sim.a`.
+pub fn compute_acc_treepm<V: Vector>(sim: &mut NBodySimulation<V>, solver: &TreePmSolver<V::F>) -> Result<(), NBodyError> {
+    solver.validate()?;
+    check_mesh_bounds(&sim.config)?;
+    // min_dist is what skips the self-pair, where the short-range kernel is infinite
+    let min_dist: V::F = sim.config.min_dist;
+    if min_dist.is_nan() || min_dist <= V::F::ZERO {
+        return Err(NBodyError::InvalidMinDist(min_dist.to_f64()));
+    }
+    let (quadtree, bb) = build_source_tree(sim);
+    let (width, height) = (bb.max_x - bb.min_x, bb.max_y - bb.min_y);
+
+    // The split follows the wider cells, so the long-range part is smooth along both axes
+    let cells: V::F = V::F::from_f64(solver.size as f64);
+    let h: V::F = (width / cells).max(height / cells);
+    let r_s: f64 = (solver.split * h).to_f64();
+    let r_cut: V::F = solver.cut * solver.split * h;
+    let (period_x, period_y) = match solver.boundary {
+        MeshBoundary::Periodic => (Some(width), Some(height)),
+        MeshBoundary::Isolated => (None, None),
+    };
+
+    // Only the nearest image of a cell is visited, so the cutoff must stay within half of either period
+    let shortest: V::F = width.min(height);
+    if solver.boundary == MeshBoundary::Periodic && r_cut * V::F::from_f64(2.) >= shortest {
+        return Err(NBodyError::InvalidTreePmCutoff((r_cut / (shortest / cells)).to_f64()));
+    }
+    let min_dist_sqrd: V::F = sim.config.min_dist_sqrd;
+
+    // Long-range part, which ignores min_dist so it stays smooth
+    let long = move |d_sqrd: V::F| {
+        V::F::from_f64(long_kernel(d_sqrd.to_f64().sqrt() / (2. * r_s), r_s))
+    };
+    compute_acc_mesh(sim, solver.size, solver.boundary, long)?;
+
+    // Short-range part from the tree. Pairs closer than min_dist cancel their mesh force instead.
+    let short = move |d_sqrd: V::F| {
+        if d_sqrd < min_dist_sqrd { return -long(d_sqrd) }
+        let x: f64 = d_sqrd.to_f64().sqrt() / (2. * r_s);
+        V::F::from_f64(short_fraction(x)) / d_sqrd.powf(V::F::from_f64(3.))
+    };
+    let r_cut_sqrd: V::F = r_cut * r_cut;
+    let mut stack = Vec::new();
+    for i in 0..sim.n {
+        let (x, y) = sim.r[i].to_xy();
+        let (mut ax, mut ay) = (V::F::ZERO, V::F::ZERO);
+
+        stack.push((&quadtree, bb));
+        while let Some((node, node_bb)) = stack.pop() {
+            // Skip cells that lie entirely beyond the cutoff
+            let gx: V::F = axis_gap(x, node_bb.min_x, node_bb.max_x, period_x);
+            let gy: V::F = axis_gap(y, node_bb.min_y, node_bb.max_y, period_y);
+            if gx * gx + gy * gy > r_cut_sqrd { continue }
+
+            let dx: V::F = nearest_image(node.x - x, period_x);
+            let dy: V::F = nearest_image(node.y - y, period_y);
+            let d_sqrd: V::F = dx * dx + dy * dy;
+            if node.is_leaf() || node_bb.width() * node_bb.width() < solver.theta * solver.theta * d_sqrd {
+                if d_sqrd > r_cut_sqrd { continue }
+                let s: V::F = node.m * short(d_sqrd);
+                ax += dx * s;
+                ay += dy * s;
+                continue;
+            }
+
+            for (quadrant, child) in node.children.iter().enumerate() {
+                if let Some(child) = child {
+                    stack.push((child, node_bb.child(quadrant)));
+                }
+            }
+        }
+        sim.a[i] += V::from_xy(ax, ay);
+    }
+    Ok(())
+}
+
+impl<V: Vector> Solver<V> for TreePmSolver<V::F> {
+    fn compute_acc(&self, sim: &mut NBodySimulation<V>) -> Result<(), NBodyError> {
+        compute_acc_treepm(sim, self)
+    }
+
+    fn name(&self) -> &'static str {
+        "tree-pm"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+    use crate::vector::{Scalar, Vector, Vector2D};
+    use crate::nbody::{NBodyConfig2D, NBodyConfigBuilder, NBodySimulation2D, MovingBody2D, BodyKind, NBodyError, generate_galaxy};
+    use crate::nbody::{MeshBoundary, compute_acc_barnes_hut, compute_acc_direct, compute_acc_pm};
+    use crate::nbody::testing::{rms};
+    use super::{TreePmSolver, compute_acc_treepm, erfc};
+
+    #[test]
+    fn test_treepm() {
+        assert!((erfc(0.) - 1.).abs() < 2e-7);
+        assert!((erfc(1.) - 0.157_299_207).abs() < 2e-7);
+        assert!((erfc(-1.) - 1.842_700_793).abs() < 2e-7);
+
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(512., 512.);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(5).build().unwrap();
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(300, config);
+        let c = MovingBody2D { r: Vector2D::from_xy(256., 256.), v: Vector2D::zero(), m: 5e6 };
+        generate_galaxy(&mut sim, &c);
+
+        compute_acc_direct(&mut sim);
+        let reference: Vec<Vector2D> = sim.a.clone();
+
+        // The tree resolves the short-range forces the mesh smooths out
+        compute_acc_pm(&mut sim, 64, MeshBoundary::Isolated).unwrap();
+        let pm_err: Scalar = rms(&sim.a, &reference);
+        compute_acc_treepm(&mut sim, &TreePmSolver::new(0.5, 64, MeshBoundary::Isolated).unwrap()).unwrap();
+        let treepm_err: Scalar = rms(&sim.a, &reference);
+        assert!(treepm_err < 0.01, "TreePM differs from direct by {}", treepm_err);
+        assert!(treepm_err < pm_err);
+
+        // Short-range forces also reach across the walls of a periodic box
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, NBodyConfigBuilder::new(10., min_r, max_r).build().unwrap());
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(500., 256.), v: Vector2D::zero(), m: 5e6 }, BodyKind::Star);
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(12., 256.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+        compute_acc_treepm(&mut sim, &TreePmSolver::new(0.5, 64, MeshBoundary::Periodic).unwrap()).unwrap();
+        let expected: Scalar = -5e6 / (24 as Scalar).powi(5);
+        assert!((sim.a[1].x - expected).abs() < 1e-2 * expected.abs());
+
+        // Cutoffs reaching past half the mesh would drop periodic images, and a zero min_dist
+        // would evaluate the short-range kernel at zero offset
+        assert_eq!(TreePmSolver::new(0.5, 16, MeshBoundary::Periodic).unwrap_err(), NBodyError::InvalidTreePmCutoff(13.5));
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, NBodyConfig2D::new(0., min_r, max_r));
+        sim.add_body(&MovingBody2D { r: Vector2D::from_xy(100., 256.), v: Vector2D::zero(), m: 1. }, BodyKind::Star);
+        let solver = TreePmSolver::new(0.5, 64, MeshBoundary::Periodic).unwrap();
+        assert_eq!(compute_acc_treepm(&mut sim, &solver), Err(NBodyError::InvalidMinDist(0.)));
+    }
+
+    /// Uniform random bodies in a periodic box from the origin to (width, height).
+    fn uniform_box(n: usize, width: Scalar, height: Scalar) -> NBodySimulation2D {
+        let min_r: Vector2D = Vector2D::from_xy(0., 0.);
+        let max_r: Vector2D = Vector2D::from_xy(width, height);
+        let config = NBodyConfigBuilder::new(10., min_r, max_r).seed(7).build().unwrap();
+        let mut sim: NBodySimulation2D = NBodySimulation2D::empty(0, config);
+        for _ in 0..n {
+            let r = Vector2D::from_xy(sim.rng.gen_range(0., width), sim.rng.gen_range(0., height));
+            let m: Scalar = sim.rng.gen_range(1e3, 1e5);
+            sim.add_body(&MovingBody2D { r, v: Vector2D::zero(), m }, BodyKind::Star);
+        }
+        sim
+    }
+
+    /// Direct sum over the box and its eight neighbouring images.
+    fn periodic_reference(sim: &NBodySimulation2D) -> Vec<Vector2D> {
+        let (width, height) = (sim.config.max_r - sim.config.min_r).to_xy();
+        (0..sim.n).map(|i| {
+            let mut a: Vector2D = Vector2D::zero();
+            for j in 0..sim.n {
+                for (ox, oy) in (-1..=1).flat_map(|ox| (-1..=1).map(move |oy| (ox, oy))) {
+                    let d: Vector2D = sim.r[j] + Vector2D::from_xy(width * ox as Scalar, height * oy as Scalar) - sim.r[i];
+                    let d_sqrd: Scalar = d.l2_sqrd();
+                    if d_sqrd < sim.config.min_dist_sqrd { continue }
+                    a += d * (sim.m[j] / d_sqrd.powi(3));
+                }
+            }
+            a
+        }).collect()
+    }
+
+    #[test]
+    fn test_treepm_periodic() {
+        let mut sim: NBodySimulation2D = uniform_box(300, 512., 512.);
+        let reference: Vec<Vector2D> = periodic_reference(&sim);
+
+        // At similar cost, a tree walk with a third of the opening angle still misses the forces across the walls
+        compute_acc_barnes_hut(&mut sim, 0.1);
+        let bh_err: Scalar = rms(&sim.a, &reference);
+        compute_acc_treepm(&mut sim, &TreePmSolver::new(0.3, 64, MeshBoundary::Periodic).unwrap()).unwrap();
+        let treepm_err: Scalar = rms(&sim.a, &reference);
+        assert!(treepm_err < 0.01, "TreePM differs from the periodic sum by {}", treepm_err);
+        assert!(treepm_err < bh_err, "TreePM error {} is not below Barnes-Hut error {}", treepm_err, bh_err);
+    }
+
+    #[test]
+    fn test_treepm_non_square() {
+        // The split follows the wider cells, so the cutoff still covers only the nearest images
+        let mut sim: NBodySimulation2D = uniform_box(300, 512., 256.);
+        let reference: Vec<Vector2D> = periodic_reference(&sim);
+        compute_acc_treepm(&mut sim, &TreePmSolver::new(0.3, 64, MeshBoundary::Periodic).unwrap()).unwrap();
+        let treepm_err: Scalar = rms(&sim.a, &reference);
+        assert!(treepm_err < 0.01, "TreePM differs from the periodic sum by {}", treepm_err);
+
+        // A cutoff of 108 would reach past half of a 192 high box
+        let mut sim: NBodySimulation2D = uniform_box(10, 512., 192.);
+        let solver = TreePmSolver::new(0.3, 64, MeshBoundary::Periodic).unwrap();
+        assert_eq!(compute_acc_treepm(&mut sim, &solver), Err(NBodyError::InvalidTreePmCutoff(36.)));
+        let solver = TreePmSolver::new(0.3, 64, MeshBoundary::Isolated).unwrap();
+        assert!(compute_acc_treepm(&mut sim, &solver).is_ok());
+    }
+}
@@ -1,34 +1,34 @@
 //! Defines a splitable bounding box
-use crate::vector::Scalar;
+use crate::vector::{Float, Scalar};
 
 /// Splitable bounding box in 2 dimensions.
 #[derive(Debug, Clone, Copy)]
-pub struct BoundingBox2D {
-    pub min_x: Scalar,
-    pub max_x: Scalar,
-    pub min_y: Scalar,
-    pub max_y: Scalar
+pub struct BoundingBox2D<F: Float = Scalar> {
+    pub min_x: F,
+    pub max_x: F,
+    pub min_y: F,
+    pub max_y: F
 }
 
 /// implementation for a splitable bounding box in 2 dimensions.
-impl BoundingBox2D {
+impl<F: Float> BoundingBox2D<F> {
     /// Gets the center X position of the bounding box.
-    pub fn cx(&self) -> Scalar {
-        (self.max_x + self.min_x) / 2.
+    pub fn cx(&self) -> F {
+        (self.max_x + self.min_x) / F::from_f64(2.)
     }
 
     /// Gets the center Y position of the bounding box.
-    pub fn cy(&self) -> Scalar {
-        (self.max_y + self.min_y) / 2.
+    pub fn cy(&self) -> F {
+        (self.max_y + self.min_y) / F::from_f64(2.)
     }
 
     /// Gets the width of this bounding box (x direction).
-    pub fn width(&self) -> Scalar {
+    pub fn width(&self) -> F {
         self.max_x - self.min_x
     }
 
     // Returns the quadtrant of a point
-    pub fn quadrant(&self, x: Scalar, y: Scalar) -> usize {
+    pub fn quadrant(&self, x: F, y: F) -> usize {
         let x_bit = (x >= self.cx()) as usize;
         let y_bit = (y >= self.cy()) as usize;
         x_bit + (y_bit << 1)
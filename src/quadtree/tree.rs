@@ -1,39 +1,41 @@
 //! Quadtree that keeps track of centers of mass.
 use super::BoundingBox2D;
-use crate::vector::{Scalar, Vector3D};
+use crate::vector::{Float, Scalar, Vector};
+#[cfg(test)]
+use crate::vector::{Vector2D, Vector3D};
 
-const EPSILON: Scalar = 1e-4;
+const EPSILON: f64 = 1e-4;
 
 /// Computes the l2 norm of a 2d vector represented by x1, y1, x2, y2
-fn l2(x1: Scalar, y1: Scalar, x2: Scalar, y2: Scalar) -> Scalar {
-    let dx: Scalar = x2 - x1;
-    let dy: Scalar = y2 - y1;
+fn l2<F: Float>(x1: F, y1: F, x2: F, y2: F) -> F {
+    let dx: F = x2 - x1;
+    let dy: F = y2 - y1;
     (dx * dx + dy * dy).sqrt()
 }
 
 /// Definition of the mass quadtree
 #[derive(Debug)]
-pub struct MassQuadtree {
-    pub x: Scalar,
-    pub y: Scalar,
-    pub m: Scalar,
+pub struct MassQuadtree<F: Float = Scalar> {
+    pub x: F,
+    pub y: F,
+    pub m: F,
     pub children: Vec<Option<Self>>,
 }
 
 /// Implementation for the mass quadtree
-impl MassQuadtree {
+impl<F: Float> MassQuadtree<F> {
     /// Constructs a child with no children
     pub fn empty() -> Self {
         Self {
-            x: 0.,
-            y: 0.,
-            m: 0.,
+            x: F::ZERO,
+            y: F::ZERO,
+            m: F::ZERO,
             children: vec![None, None, None, None]
         }
     }
 
     // Constructs a new child under a node
-    pub fn new_child(&mut self, quadrant: usize, x: Scalar, y: Scalar, m: Scalar) {
+    pub fn new_child(&mut self, quadrant: usize, x: F, y: F, m: F) {
         // println!("New child ({}, {}, {}) under ({}, {}, {}) in quad {}", x, y, m, self.x, self.y, self.m, quadrant);
         self.children[quadrant] = Some(Self {
             x,
@@ -44,31 +46,33 @@ impl MassQuadtree {
     }
     
     /// Constructs a quadtree for the given bounds and list of points
-    pub fn new(r: &Vec<Vector3D>, m: &Vec<Scalar>, bb: BoundingBox2D) -> Self {
+    pub fn new<V: Vector<F = F>>(r: &[V], m: &[F], bb: BoundingBox2D<F>) -> Self {
         let mut root = Self::empty();
         for i in 0..r.len() {
-            root.insert(r[i].x, r[i].y, m[i], bb);
+            let (x, y) = r[i].to_xy();
+            root.insert(x, y, m[i], bb);
         }
         root
     }
 
     // Updates the center of mass
-    pub fn update_com(&mut self, x: Scalar, y: Scalar, m: Scalar) {
-        let total_m: Scalar = self.m + m;
+    pub fn update_com(&mut self, x: F, y: F, m: F) {
+        let total_m: F = self.m + m;
         self.x = (self.m * self.x + m * x) / total_m;
         self.y = (self.m * self.y + m * y) / total_m;
         self.m = total_m;
     }
     
     /// Inserts a point into the quadtree.
-    pub fn insert(&mut self, x: Scalar, y: Scalar, m: Scalar, bb: BoundingBox2D) {
+    #[allow(clippy::redundant_pattern_matching)]
+    pub fn insert(&mut self, x: F, y: F, m: F, bb: BoundingBox2D<F>) {
         // Edge cases: if inserting empty objects or inserting the first element of the tree
-        if m == 0. { return }
-        if self.m == 0. { self.x = x; self.y = y; self.m = m; return }
+        if m == F::ZERO { return }
+        if self.m == F::ZERO { self.x = x; self.y = y; self.m = m; return }
 
         // Find the parent to insert this node under
         let mut parent: &mut Self = self;
-        let mut parent_bb: BoundingBox2D = bb;
+        let mut parent_bb: BoundingBox2D<F> = bb;
         let mut quadrant: usize = parent_bb.quadrant(x, y);
         while let Some(_) = &mut parent.children[quadrant] {
             // Update the parent's center of mass
@@ -87,7 +91,7 @@ impl MassQuadtree {
             let (px, py, pm) = (parent.x, parent.y, parent.m);
 
             // Edge case: if the parent is too close to the child, don't insert as child
-            if (px - x).abs() < EPSILON && (py - y).abs() < EPSILON { return }
+            if (px - x).abs() < F::from_f64(EPSILON) && (py - y).abs() < F::from_f64(EPSILON) { return }
 
             // Find the center of mass between the two
             parent.update_com(x, y, m);
@@ -125,17 +129,17 @@ impl MassQuadtree {
 }
 
 /// Iterator for iterating over all nearby nodes of the tree
-pub struct MassQuadtreeIterator<'a> {
-    x: Scalar,
-    y: Scalar,
-    theta: Scalar,
-    stack: Vec<(&'a MassQuadtree, BoundingBox2D)>
+pub struct MassQuadtreeIterator<'a, F: Float = Scalar> {
+    x: F,
+    y: F,
+    theta: F,
+    stack: Vec<(&'a MassQuadtree<F>, BoundingBox2D<F>)>
 }
 
 /// Implementation of the constructor for the mass quadtree iterator.
-impl<'a> MassQuadtreeIterator<'a> {
+impl<'a, F: Float> MassQuadtreeIterator<'a, F> {
     /// Constructs a new iterator with the stack initialized to the root.
-    pub fn new(x: Scalar, y: Scalar, theta: Scalar, tree: &'a MassQuadtree, bb: BoundingBox2D) -> Self {
+    pub fn new(x: F, y: F, theta: F, tree: &'a MassQuadtree<F>, bb: BoundingBox2D<F>) -> Self {
         Self {
             x,
             y,
@@ -146,8 +150,8 @@ impl<'a> MassQuadtreeIterator<'a> {
 }
 
 /// Implements the iterator
-impl<'a> Iterator for MassQuadtreeIterator<'a> {
-    type Item = &'a MassQuadtree;
+impl<'a, F: Float> Iterator for MassQuadtreeIterator<'a, F> {
+    type Item = &'a MassQuadtree<F>;
 
     /// Gets the next node that should count towards the force calculation for the current particle.
     /// 
@@ -159,12 +163,13 @@ impl<'a> Iterator for MassQuadtreeIterator<'a> {
     /// The parameter θ determines the accuracy of the simulation;
     /// larger values of θ increase the speed of the simulation but decreases its accuracy.
     /// If θ = 0, no internal node is treated as a single body and the algorithm degenerates to a direct-sum algorithm.
-    fn next(&mut self) -> Option<&'a MassQuadtree> {
+    #[allow(clippy::single_match)]
+    fn next(&mut self) -> Option<&'a MassQuadtree<F>> {
         while !self.stack.is_empty() {
             let (node, bb) = self.stack.pop()?;
             
-            let d: Scalar = l2(node.x, node.y, self.x, self.y);
-            let s: Scalar = bb.width();
+            let d: F = l2(node.x, node.y, self.x, self.y);
+            let s: F = bb.width();
             if s / d < self.theta || node.is_leaf() { return Some(node) }
             
             // If not far enough away, add children to the stack.
@@ -211,3 +216,16 @@ fn test_quadtree() {
         println!("Node: ({}, {}, {})", node.x, node.y, node.m);
     }
 }
+
+#[test]
+fn test_quadtree_2d() {
+    // The tree only uses x and y, so 2D and 3D bodies give the same tree
+    let r2: Vec<Vector2D> = vec![Vector2D { x: 265.56293, y: 263.4189 }, Vector2D { x: 250.0, y: 250.0 }];
+    let r3: Vec<Vector3D> = vec![Vector3D { x: 265.56293, y: 263.4189, z: 7. }, Vector3D { x: 250.0, y: 250.0, z: -3. }];
+    let m: Vec<Scalar> = vec![0.4261353, 5000000.0];
+    let bb: BoundingBox2D = BoundingBox2D{min_x: 0., max_x: 500., min_y: 0., max_y: 500.};
+    let tree2 = MassQuadtree::new(&r2, &m, bb);
+    let tree3 = MassQuadtree::new(&r3, &m, bb);
+    assert_eq!((tree2.x, tree2.y, tree2.m), (tree3.x, tree3.y, tree3.m));
+    assert_eq!(tree2.m, 0.4261353 + 5000000.0);
+}
@@ -1,8 +1,9 @@
-//! Custom 3D vector struct.
-use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+//! Vector trait shared by the 2D and 3D vectors.
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
 use std::fmt::Debug;
+use super::float::Float;
 
-/// Scalar type: configure floating point precision here.
+/// Default floating point precision.
 pub type Scalar = f32;
 
 /// Vector type that supports linear combinations, cloning, and l2 norm.
@@ -14,13 +15,40 @@ pub trait Vector:
         Debug +
         Add<Output = Self> +
         AddAssign +
-        Mul<Scalar, Output = Self> +
-        MulAssign<Scalar> +
+        Mul<<Self as Vector>::F, Output = Self> +
+        MulAssign<<Self as Vector>::F> +
+        Div<<Self as Vector>::F, Output = Self> +
+        Neg<Output = Self> +
         Sub<Output = Self> +
         SubAssign {
+    /// Floating point type of the components.
+    type F: Float;
+
+    /// Result of the cross product: a scalar in 2D and a vector in 3D.
+    type Cross: Copy + Debug + PartialEq;
+
+    /// Number of components in the vector.
+    const DIM: usize;
+
     fn zero() -> Self;
-    fn from_xy(x: Scalar, y: Scalar) -> Self;
-    fn to_xy(self) -> (Scalar, Scalar);
-    fn l2_sqrd(self) -> Scalar;
+    fn from_xy(x: Self::F, y: Self::F) -> Self;
+    fn to_xy(self) -> (Self::F, Self::F);
+    fn l2_sqrd(self) -> Self::F;
+    fn dot(self, rhs: Self) -> Self::F;
+    fn cross(self, rhs: Self) -> Self::Cross;
     fn in_bounds(self, min: &Self, max: &Self) -> bool;
+    fn component(self, k: usize) -> Self::F;
+    fn set_component(&mut self, k: usize, value: Self::F);
+
+    /// Gets the l2 norm of the vector.
+    fn norm(self) -> Self::F {
+        self.l2_sqrd().sqrt()
+    }
+
+    /// Gets the unit vector in the same direction, or zero for the zero vector.
+    fn normalize(self) -> Self {
+        let norm = self.norm();
+        if norm == Self::F::ZERO { return Self::zero() }
+        self / norm
+    }
 }
@@ -0,0 +1,89 @@
+//! Floating point types usable as vector components.
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Floating point precision of a simulation, implemented for `f32` and `f64`.
+pub trait Float:
+        Sized +
+        Copy +
+        Clone +
+        Default +
+        PartialEq +
+        PartialOrd +
+        Debug +
+        Display +
+        Send +
+        Sync +
+        Sum +
+        Add<Output = Self> +
+        AddAssign +
+        Sub<Output = Self> +
+        SubAssign +
+        Mul<Output = Self> +
+        MulAssign +
+        Div<Output = Self> +
+        DivAssign +
+        Neg<Output = Self> +
+        'static {
+    const ZERO: Self;
+    const ONE: Self;
+    const EPSILON: Self;
+    const INFINITY: Self;
+    const PI: Self;
+
+    /// Converts from `f64`, rounding if the type is less precise.
+    fn from_f64(x: f64) -> Self;
+    /// Converts to `f64` without loss.
+    fn to_f64(self) -> f64;
+
+    fn sqrt(self) -> Self;
+    fn cbrt(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn exp(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn abs(self) -> Self;
+    fn floor(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn rem_euclid(self, rhs: Self) -> Self;
+    fn is_finite(self) -> bool;
+    fn is_nan(self) -> bool;
+    /// Total order that also sorts NaN, as in the IEEE 754 totalOrder predicate.
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+macro_rules! impl_float {
+    ($t:ident) => {
+        impl Float for $t {
+            const ZERO: Self = 0.;
+            const ONE: Self = 1.;
+            const EPSILON: Self = $t::EPSILON;
+            const INFINITY: Self = $t::INFINITY;
+            const PI: Self = std::$t::consts::PI;
+
+            fn from_f64(x: f64) -> Self { x as $t }
+            fn to_f64(self) -> f64 { self as f64 }
+
+            fn sqrt(self) -> Self { $t::sqrt(self) }
+            fn cbrt(self) -> Self { $t::cbrt(self) }
+            fn powf(self, n: Self) -> Self { $t::powf(self, n) }
+            fn exp(self) -> Self { $t::exp(self) }
+            fn sin(self) -> Self { $t::sin(self) }
+            fn cos(self) -> Self { $t::cos(self) }
+            fn abs(self) -> Self { $t::abs(self) }
+            fn floor(self) -> Self { $t::floor(self) }
+            fn min(self, other: Self) -> Self { $t::min(self, other) }
+            fn max(self, other: Self) -> Self { $t::max(self, other) }
+            fn rem_euclid(self, rhs: Self) -> Self { $t::rem_euclid(self, rhs) }
+            fn is_finite(self) -> bool { $t::is_finite(self) }
+            fn is_nan(self) -> bool { $t::is_nan(self) }
+            fn total_cmp(&self, other: &Self) -> Ordering { $t::total_cmp(self, other) }
+        }
+    };
+}
+
+impl_float!(f32);
+impl_float!(f64);
@@ -1,6 +1,10 @@
 //! Shared utilities.
 pub mod base;
+pub mod float;
+pub mod vector2d;
 pub mod vector3d;
 
 pub use self::base::{Scalar, Vector};
+pub use self::float::Float;
+pub use self::vector2d::Vector2D;
 pub use self::vector3d::Vector3D;
@@ -0,0 +1,164 @@
+//! Custom 2D vector struct.
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+use super::{Float, Scalar, Vector};
+
+/// Generic 2D vector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vector2D<F: Float = Scalar> {
+    pub x: F,
+    pub y: F,
+}
+
+impl<F: Float> Mul<F> for Vector2D<F> {
+    type Output = Self;
+    fn mul(self, rhs: F) -> Self {
+        Self {
+            x: self.x * rhs,
+            y: self.y * rhs,
+        }
+    }
+}
+
+impl<F: Float> MulAssign<F> for Vector2D<F> {
+    fn mul_assign(&mut self, rhs: F) {
+        *self = Self {
+            x: self.x * rhs,
+            y: self.y * rhs,
+        };
+    }
+}
+
+impl<F: Float> Div<F> for Vector2D<F> {
+    type Output = Self;
+    fn div(self, rhs: F) -> Self {
+        Self {
+            x: self.x / rhs,
+            y: self.y / rhs,
+        }
+    }
+}
+
+impl<F: Float> Neg for Vector2D<F> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
+impl<F: Float> Add for Vector2D<F> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+        }
+    }
+}
+
+impl<F: Float> AddAssign for Vector2D<F> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+        };
+    }
+}
+
+impl<F: Float> Sub for Vector2D<F> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+        }
+    }
+}
+
+impl<F: Float> SubAssign for Vector2D<F> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = Self {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+        };
+    }
+}
+
+impl<F: Float> Vector for Vector2D<F> {
+    type F = F;
+    /// The z component of the cross product of the vectors embedded in 3D.
+    type Cross = F;
+    const DIM: usize = 2;
+
+    fn zero() -> Self {
+        Self {
+            x: F::ZERO,
+            y: F::ZERO,
+        }
+    }
+
+    fn from_xy(x: F, y: F) -> Self {
+        Self { x, y }
+    }
+
+    fn to_xy(self) -> (F, F) {
+        (self.x, self.y)
+    }
+
+    fn l2_sqrd(self) -> F {
+        self.x * self.x + self.y * self.y
+    }
+
+    fn dot(self, rhs: Self) -> F {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    fn cross(self, rhs: Self) -> F {
+        self.x * rhs.y - self.y * rhs.x
+    }
+
+    fn in_bounds(self, min: &Self, max: &Self) -> bool {
+        self.x >= min.x && self.x <= max.x &&
+        self.y >= min.y && self.y <= max.y
+    }
+
+    fn component(self, k: usize) -> F {
+        match k {
+            0 => self.x,
+            1 => self.y,
+            _ => panic!("Vector2D has no component {}", k),
+        }
+    }
+
+    fn set_component(&mut self, k: usize, value: F) {
+        match k {
+            0 => self.x = value,
+            1 => self.y = value,
+            _ => panic!("Vector2D has no component {}", k),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ops() {
+        let v1 = Vector2D { x: 3., y: 4. };
+        let v2 = Vector2D { x: 1., y: 2. };
+        let s: Scalar = 2.;
+
+        assert_eq!(v1 + v2, Vector2D { x: 4., y: 6. });
+        assert_eq!(-(v1 - v2) * s, Vector2D { x: -4., y: -4. });
+        assert_eq!(v1 / s, Vector2D { x: 1.5, y: 2. });
+        assert_eq!(v1.dot(v2), 11.);
+        assert_eq!(v1.cross(v2), 2.);
+        assert_eq!(v1.norm(), 5.);
+        assert_eq!(v1.normalize(), Vector2D { x: 0.6, y: 0.8 });
+        assert_eq!(Vector2D::<Scalar>::zero().normalize(), Vector2D::zero());
+    }
+}
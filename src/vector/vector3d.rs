@@ -1,19 +1,19 @@
 //! Custom 3D vector struct.
 use std::fmt::Debug;
-use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
-use super::{Scalar, Vector};
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+use super::{Float, Scalar, Vector};
 
 /// Generic 3D vector.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Vector3D {
-    pub x: Scalar,
-    pub y: Scalar,
-    pub z: Scalar,
+pub struct Vector3D<F: Float = Scalar> {
+    pub x: F,
+    pub y: F,
+    pub z: F,
 }
 
-impl Mul<Scalar> for Vector3D {
+impl<F: Float> Mul<F> for Vector3D<F> {
     type Output = Self;
-    fn mul(self, rhs: Scalar) -> Self {
+    fn mul(self, rhs: F) -> Self {
         Self {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -22,8 +22,8 @@ impl Mul<Scalar> for Vector3D {
     }
 }
 
-impl MulAssign<Scalar> for Vector3D {
-    fn mul_assign(&mut self, rhs: Scalar) {
+impl<F: Float> MulAssign<F> for Vector3D<F> {
+    fn mul_assign(&mut self, rhs: F) {
         *self = Self {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -32,7 +32,29 @@ impl MulAssign<Scalar> for Vector3D {
     }
 }
 
-impl Add for Vector3D {
+impl<F: Float> Div<F> for Vector3D<F> {
+    type Output = Self;
+    fn div(self, rhs: F) -> Self {
+        Self {
+            x: self.x / rhs,
+            y: self.y / rhs,
+            z: self.z / rhs,
+        }
+    }
+}
+
+impl<F: Float> Neg for Vector3D<F> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl<F: Float> Add for Vector3D<F> {
     type Output = Self;
     fn add(self, rhs: Self) -> Self {
         Self {
@@ -43,7 +65,7 @@ impl Add for Vector3D {
     }
 }
 
-impl AddAssign for Vector3D {
+impl<F: Float> AddAssign for Vector3D<F> {
     fn add_assign(&mut self, rhs: Self) {
         *self = Self {
             x: self.x + rhs.x,
@@ -53,7 +75,7 @@ impl AddAssign for Vector3D {
     }
 }
 
-impl Sub for Vector3D {
+impl<F: Float> Sub for Vector3D<F> {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self {
         Self {
@@ -64,7 +86,7 @@ impl Sub for Vector3D {
     }
 }
 
-impl SubAssign for Vector3D {
+impl<F: Float> SubAssign for Vector3D<F> {
     fn sub_assign(&mut self, rhs: Self) {
         *self = Self {
             x: self.x - rhs.x,
@@ -74,36 +96,70 @@ impl SubAssign for Vector3D {
     }
 }
 
-impl Vector for Vector3D {
+impl<F: Float> Vector for Vector3D<F> {
+    type F = F;
+    type Cross = Self;
+    const DIM: usize = 3;
+
     fn zero() -> Self {
         Self {
-            x: 0.,
-            y: 0.,
-            z: 0.
+            x: F::ZERO,
+            y: F::ZERO,
+            z: F::ZERO
         }
     }
     
-    fn from_xy(x: Scalar, y: Scalar) -> Self {
+    fn from_xy(x: F, y: F) -> Self {
         Self {
             x,
             y,
-            z: 0.
+            z: F::ZERO
         }
     }
 
-    fn to_xy(self) -> (Scalar, Scalar) {
+    fn to_xy(self) -> (F, F) {
         (self.x, self.y)
     }
 
-    fn l2_sqrd(self) -> Scalar {
+    fn l2_sqrd(self) -> F {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
+    fn dot(self, rhs: Self) -> F {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    fn cross(self, rhs: Self) -> Self {
+        Self {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+
     fn in_bounds(self, min: &Self, max: &Self) -> bool {
         self.x >= min.x && self.x <= max.x &&
         self.y >= min.y && self.y <= max.y &&
         self.z >= min.z && self.z <= max.z
     }
+
+    fn component(self, k: usize) -> F {
+        match k {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Vector3D has no component {}", k),
+        }
+    }
+
+    fn set_component(&mut self, k: usize, value: F) {
+        match k {
+            0 => self.x = value,
+            1 => self.y = value,
+            2 => self.z = value,
+            _ => panic!("Vector3D has no component {}", k),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -140,6 +196,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_in_bounds() {
         let min = Vector3D { x: 0., y: 0., z: 0., };
         let max = Vector3D { x: 500., y: 500., z: 0., };